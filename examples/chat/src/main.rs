@@ -1,8 +1,8 @@
 use std::collections::{HashMap, HashSet};
 
 use puck::core::router::match_url::{Match, Segment};
-use puck::core::router::{Route, Router};
-use puck::core::{Core, UsedStream};
+use puck::core::router::{MakeRouter, Route, Router};
+use puck::core::{Core, ServerConfig, UsedStream};
 use puck::lunatic::process::Request;
 use puck::lunatic::process::{AbstractProcess, ProcessRef, ProcessRequest, StartProcess};
 use puck::lunatic::serializer::Bincode;
@@ -469,27 +469,34 @@ impl ProcessRequest<CoordinatorInput> for ChatServerState {
 fn main() {
     let coordinator = ChatServerState::start((), None);
 
-    let router = Router::new()
-        .route(Route::new(
-            |req| Match::new().at(Segment::Static("js")).does_match(req.url()),
-            |_, stream, _| stream.respond(puck_liveview::init::js()).unwrap(),
-        ))
-        .route(Route::new(
-            |req| Match::new().at(Segment::Static("")).does_match(req.url()),
-            |_, stream, _| stream.respond(puck_liveview::init::index()).unwrap(),
-        ))
-        .route(Route::new(
-            |req| Match::new().at(Segment::Static("ws")).does_match(req.url()),
-            |req, stream, state| {
-                let websocket = match stream.upgrade(&req) {
-                    Ok(w) => w,
-                    Err(stream) => return stream,
-                };
-                liveview(websocket, state)
-            },
-        ));
-
-    Core::bind("localhost:8081", coordinator)
+    Core::bind("localhost:8081", coordinator, ServerConfig::default())
         .expect("failed to serve")
-        .serve_router(router);
+        .serve_router(App);
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct App;
+
+impl MakeRouter<ProcessRef<ChatServerState>> for App {
+    fn make_router(&self) -> Router<ProcessRef<ChatServerState>> {
+        Router::new()
+            .route(Route::new(
+                |req| Match::new().at(Segment::Static("js")).does_match(req.url()),
+                |_, stream, _| stream.respond(puck_liveview::init::js()).unwrap(),
+            ))
+            .route(Route::new(
+                |req| Match::new().at(Segment::Static("")).does_match(req.url()),
+                |req, stream, _| stream.respond(puck_liveview::init::index(&req)).unwrap(),
+            ))
+            .route(Route::new(
+                |req| Match::new().at(Segment::Static("ws")).does_match(req.url()),
+                |req, stream, state| {
+                    let websocket = match stream.upgrade(&req) {
+                        Ok(w) => w,
+                        Err(stream) => return stream,
+                    };
+                    liveview(websocket, state)
+                },
+            ))
+    }
 }
@@ -8,9 +8,9 @@ use puck::{
     core::{
         router::{
             match_url::{self, Match},
-            Route, Router,
+            MakeRouter, Route, Router,
         },
-        Core,
+        Core, ServerConfig,
     },
     request::Method,
     Response,
@@ -20,118 +20,131 @@ use puck::{
 fn main(_: Mailbox<()>) {
     let proc = List::start(vec![], None);
 
-    let router = Router::<ProcessRef<List>>::new()
-        .route(Route::new(
-            |request| {
-                request.method() == &Method::Get
-                    && Match::new()
-                        .at(match_url::path("submit"))
-                        .does_match(request.url())
-            },
-            |mut _request, stream, _state| {
-                stream
-                    .respond(
-                        Response::build()
-                            .headers(vec![("Content-Type".to_string(), "text/html".to_string())])
-                            .body(Body::from_string(
-                                html().head(head().child(title("Submit a message"))).body(
-                                    body().child(
-                                        form()
-                                            .attribute(malvolio::prelude::Method::Post)
-                                            .child(input().attribute(Name::new("message")))
-                                            .child(input().attribute(Type::Submit)),
+    Core::bind("localhost:8080", proc, ServerConfig::default())
+        .expect("failed to launch")
+        .serve_router(App);
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+struct App;
+
+impl MakeRouter<ProcessRef<List>> for App {
+    fn make_router(&self) -> Router<ProcessRef<List>> {
+        Router::<ProcessRef<List>>::new()
+            .route(Route::new(
+                |request| {
+                    request.method() == &Method::Get
+                        && Match::new()
+                            .at(match_url::path("submit"))
+                            .does_match(request.url())
+                },
+                |mut _request, stream, _state| {
+                    stream
+                        .respond(
+                            Response::build()
+                                .headers(vec![(
+                                    "Content-Type".to_string(),
+                                    "text/html".to_string(),
+                                )])
+                                .body(Body::from_string(
+                                    html().head(head().child(title("Submit a message"))).body(
+                                        body().child(
+                                            form()
+                                                .attribute(malvolio::prelude::Method::Post)
+                                                .child(input().attribute(Name::new("message")))
+                                                .child(input().attribute(Type::Submit)),
+                                        ),
                                     ),
-                                ),
-                            ))
-                            .build(),
-                    )
-                    .unwrap()
-            },
-        ))
-        .route(Route::new(
-            |request| {
-                request.method() == &Method::Post
-                    && Match::new()
-                        .at(match_url::path("submit"))
-                        .does_match(request.url())
-            },
-            |mut request, stream, state| {
-                let res = request.take_body().into_string().unwrap();
+                                ))
+                                .build(),
+                        )
+                        .unwrap()
+                },
+            ))
+            .route(Route::new(
+                |request| {
+                    request.method() == &Method::Post
+                        && Match::new()
+                            .at(match_url::path("submit"))
+                            .does_match(request.url())
+                },
+                |mut request, stream, state| {
+                    let res = request.take_body().into_string().unwrap();
 
-                if res.starts_with("message=") {
-                    // beware of how utf-8 works if you copy this
-                    let seg = res.split_at("message=".len()).1;
+                    if res.starts_with("message=") {
+                        // beware of how utf-8 works if you copy this
+                        let seg = res.split_at("message=".len()).1;
 
-                    match state.request(Msg::Add(seg.to_string())) {
-                        Reply::Items(_) => unreachable!(),
-                        Reply::Added => stream
-                            .respond(
-                                Response::build()
-                                    .headers(vec![(
-                                        "Content-Type".to_string(),
-                                        "text/html".to_string(),
-                                    )])
-                                    .body(Body::from_string(
-                                        html()
-                                            .head(head().child(title("Submit a message")))
-                                            .body(body().child(h1("Added that item"))),
-                                    ))
-                                    .build(),
-                            )
-                            .unwrap(),
+                        match state.request(Msg::Add(seg.to_string())) {
+                            Reply::Items(_) => unreachable!(),
+                            Reply::Added => stream
+                                .respond(
+                                    Response::build()
+                                        .headers(vec![(
+                                            "Content-Type".to_string(),
+                                            "text/html".to_string(),
+                                        )])
+                                        .body(Body::from_string(
+                                            html()
+                                                .head(head().child(title("Submit a message")))
+                                                .body(body().child(h1("Added that item"))),
+                                        ))
+                                        .build(),
+                                )
+                                .unwrap(),
+                        }
+                    } else {
+                        stream.respond(puck::Response::bad_request()).unwrap()
                     }
-                } else {
-                    stream.respond(puck::err_400()).unwrap()
-                }
-            },
-        ))
-        .route(Route::new(
-            |request| {
-                Match::new()
-                    .at(match_url::path("read"))
-                    .at(match_url::any_integer())
-                    .does_match(request.url())
-            },
-            |request, stream, state| {
-                let segment = request.url().path().split_at("/read/".len()).1;
-                let n = segment.parse::<usize>().unwrap();
-                let res = state.request(Msg::LastN(n));
-                let items = match res {
-                    Reply::Items(items) => items,
-                    Reply::Added => unreachable!(),
-                };
-                stream
-                    .respond(
-                        puck::Response::build()
-                            .headers(vec![("Content-Type".to_string(), "text/html".to_string())])
-                            .body(Body::from_string(
-                                html().head(head().child(title("Message list"))).body(
-                                    body().child(h1("Message list")).map(|body| {
-                                        if items.is_empty() {
-                                            body.child(p().text("There are no messages yet."))
-                                        } else {
-                                            body.children(
-                                                items.into_iter().map(|item| {
+                },
+            ))
+            .route(Route::capturing(
+                |request| {
+                    Match::new()
+                        .at(match_url::path("read"))
+                        .at(match_url::int_param("n"))
+                        .captures(request.url())
+                },
+                |request, stream, state| {
+                    let n = match request.params().get::<usize>("n") {
+                        Some(n) => n,
+                        None => return stream.respond(puck::Response::bad_request()).unwrap(),
+                    };
+                    let res = state.request(Msg::LastN(n));
+                    let items = match res {
+                        Reply::Items(items) => items,
+                        Reply::Added => unreachable!(),
+                    };
+                    stream
+                        .respond(
+                            puck::Response::build()
+                                .headers(vec![(
+                                    "Content-Type".to_string(),
+                                    "text/html".to_string(),
+                                )])
+                                .body(Body::from_string(
+                                    html().head(head().child(title("Message list"))).body(
+                                        body().child(h1("Message list")).map(|body| {
+                                            if items.is_empty() {
+                                                body.child(p().text("There are no messages yet."))
+                                            } else {
+                                                body.children(items.into_iter().map(|item| {
                                                     p().text(format!("Item: {}", item))
-                                                }),
-                                            )
-                                        }
-                                    }),
-                                ),
-                            ))
-                            .build(),
-                    )
-                    .unwrap()
-            },
-        ))
-        .route(Route::new(
-            |_request| true,
-            |_request, stream, _state| stream.respond(puck::err_404()).unwrap(),
-        ));
-
-    Core::bind("localhost:8080", proc)
-        .expect("failed to launch")
-        .serve_router(router);
+                                                }))
+                                            }
+                                        }),
+                                    ),
+                                ))
+                                .build(),
+                        )
+                        .unwrap()
+                },
+            ))
+            .route(Route::new(
+                |_request| true,
+                |_request, stream, _state| stream.respond(puck::Response::not_found()).unwrap(),
+            ))
+    }
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
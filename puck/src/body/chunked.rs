@@ -0,0 +1,262 @@
+//! The `chunked` transfer coding, which is used to send bodies whose length is not known in
+//! advance.
+//!
+//! See [RFC 9112 section 7.1](https://www.rfc-editor.org/rfc/rfc9112#section-7.1).
+
+use std::io::{self, Read, Write};
+
+/// The longest line (chunk size, chunk extensions or trailer field) which will be accepted.
+const MAX_LINE_LENGTH: usize = 4096;
+
+/// The most bytes of trailer fields which will be accepted. Trailers are thrown away, so they are
+/// not counted towards any limit on the size of the body.
+const MAX_TRAILERS_LENGTH: usize = 16 * 1024;
+
+#[derive(Debug)]
+/// Decodes a body sent using the `chunked` transfer coding.
+///
+/// Chunk extensions and trailer fields are read (so that the underlying reader is left directly
+/// after the end of the body) but otherwise ignored.
+pub struct ChunkedDecoder<R> {
+    reader: R,
+    state: DecoderState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DecoderState {
+    /// Expecting the line containing the size of the next chunk.
+    Size,
+    /// Part-way through a chunk with the given number of bytes left.
+    Data(u64),
+    /// Expecting the line break which terminates a chunk.
+    DataEnd,
+    /// Expecting trailer fields (having read the given number of bytes of them), or the blank
+    /// line which ends the body.
+    Trailers(usize),
+    /// The body has been read.
+    Done,
+}
+
+impl<R> ChunkedDecoder<R>
+where
+    R: Read,
+{
+    /// Construct a new decoder, which will read a chunked body from `reader`.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            state: DecoderState::Size,
+        }
+    }
+
+    /// Reads a line, without the trailing line break.
+    ///
+    /// This reads a byte at a time, so that nothing after the line is consumed.
+    fn read_line(&mut self) -> io::Result<Vec<u8>> {
+        let mut line = Vec::new();
+        let mut byte = [0];
+        loop {
+            if self.reader.read(&mut byte)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            if byte[0] == b'\n' {
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                return Ok(line);
+            }
+            if line.len() == MAX_LINE_LENGTH {
+                return Err(invalid_data("chunked encoding line too long"));
+            }
+            line.push(byte[0]);
+        }
+    }
+}
+
+impl<R> Read for ChunkedDecoder<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.state {
+                DecoderState::Size => {
+                    let line = self.read_line()?;
+                    let size = line
+                        .split(|byte| *byte == b';')
+                        .next()
+                        .and_then(parse_size)
+                        .ok_or_else(|| invalid_data("invalid chunk size"))?;
+                    self.state = if size == 0 {
+                        DecoderState::Trailers(0)
+                    } else {
+                        DecoderState::Data(size)
+                    };
+                }
+                DecoderState::Data(remaining) => {
+                    if buf.is_empty() {
+                        return Ok(0);
+                    }
+                    let max = (remaining.min(buf.len() as u64)) as usize;
+                    let read = self.reader.read(&mut buf[..max])?;
+                    if read == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    let remaining = remaining - read as u64;
+                    self.state = if remaining == 0 {
+                        DecoderState::DataEnd
+                    } else {
+                        DecoderState::Data(remaining)
+                    };
+                    return Ok(read);
+                }
+                DecoderState::DataEnd => {
+                    if !self.read_line()?.is_empty() {
+                        return Err(invalid_data("chunk was longer than its stated size"));
+                    }
+                    self.state = DecoderState::Size;
+                }
+                DecoderState::Trailers(read) => {
+                    let line = self.read_line()?;
+                    if line.is_empty() {
+                        self.state = DecoderState::Done;
+                        continue;
+                    }
+                    if !line.contains(&b':') {
+                        return Err(invalid_data("invalid trailer field"));
+                    }
+                    let read = read + line.len();
+                    if read > MAX_TRAILERS_LENGTH {
+                        return Err(invalid_data("trailer fields too long"));
+                    }
+                    self.state = DecoderState::Trailers(read);
+                }
+                DecoderState::Done => return Ok(0),
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Encodes everything written to it using the `chunked` transfer coding.
+///
+/// Every call to `write` produces one chunk. [ChunkedEncoder::finish] must be called once the
+/// whole body has been written, to write the final (empty) chunk.
+pub struct ChunkedEncoder<W> {
+    writer: W,
+}
+
+impl<W> ChunkedEncoder<W>
+where
+    W: Write,
+{
+    /// Construct a new encoder, which will write the encoded body to `writer`.
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Writes the last chunk, which marks the end of the body, and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.write_all(b"0\r\n\r\n")?;
+        Ok(self.writer)
+    }
+}
+
+impl<W> Write for ChunkedEncoder<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // an empty chunk would end the body
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        write!(self.writer, "{:X}\r\n", buf.len())?;
+        self.writer.write_all(buf)?;
+        self.writer.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Parses a chunk size, which must be nothing but hex digits. `u64::from_str_radix` would also
+/// accept a sign, and other servers (e.g. a proxy in front of this one) might not, so they would
+/// disagree about where the chunk ends.
+fn parse_size(size: &[u8]) -> Option<u64> {
+    if size.is_empty() || !size.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    u64::from_str_radix(std::str::from_utf8(size).ok()?, 16).ok()
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, ErrorKind, Read, Write};
+
+    use super::{ChunkedDecoder, ChunkedEncoder};
+
+    #[lunatic::test]
+    fn test_decode_with_extensions_and_trailers() {
+        let mut reader = Cursor::new(
+            "4;name=value\r\nWiki\r\n6\r\npedia \r\nE\r\nin \r\n\r\nchunks.\r\n0\r\n\
+            Expires: never\r\n\r\nGET / HTTP/1.1",
+        );
+
+        let mut decoded = String::new();
+        ChunkedDecoder::new(&mut reader)
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "Wikipedia in \r\n\r\nchunks.");
+
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "GET / HTTP/1.1");
+    }
+
+    #[lunatic::test]
+    fn test_decode_rejects_invalid_size() {
+        for size in ["zz", "+3", " 3", "3 ", "\t3", "", "-0"] {
+            let mut decoded = Vec::new();
+            let body = format!("{}\r\nabc\r\n0\r\n\r\n", size);
+            assert!(
+                ChunkedDecoder::new(Cursor::new(body))
+                    .read_to_end(&mut decoded)
+                    .is_err(),
+                "{:?}",
+                size
+            );
+        }
+    }
+
+    #[lunatic::test]
+    fn test_decode_limits_trailers() {
+        let trailers = "a: b\r\n".repeat(5000);
+        let mut decoded = Vec::new();
+        let error = ChunkedDecoder::new(Cursor::new(format!("0\r\n{}\r\n", trailers)))
+            .read_to_end(&mut decoded)
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[lunatic::test]
+    fn test_encode_roundtrip() {
+        let mut encoder = ChunkedEncoder::new(Vec::new());
+        encoder.write_all(b"hello ").unwrap();
+        encoder.write_all(b"world").unwrap();
+        let encoded = encoder.finish().unwrap();
+        assert_eq!(encoded, b"6\r\nhello \r\n5\r\nworld\r\n0\r\n\r\n");
+
+        let mut decoded = String::new();
+        ChunkedDecoder::new(Cursor::new(encoded))
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "hello world");
+    }
+}
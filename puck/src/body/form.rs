@@ -0,0 +1,257 @@
+//! Reading `application/x-www-form-urlencoded` data, which is how HTML forms are submitted (and
+//! how query strings are usually encoded).
+
+use std::{io, iter::FromIterator};
+
+use serde::de::DeserializeOwned;
+use url::form_urlencoded;
+
+use crate::response::status::StatusCode;
+
+use super::{read_error_status, Body};
+
+/// The largest body (in bytes) which [Body::form] will read.
+pub const DEFAULT_FORM_LIMIT: usize = 64 * 1024;
+
+/// Decoded `application/x-www-form-urlencoded` data: a list of names and values, in the order
+/// they were sent.
+///
+/// The same name may appear several times (e.g. for a `<select multiple>` element, or a set of
+/// checkboxes), so [Form::get_all] returns every value for a name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Form {
+    pairs: Vec<(String, String)>,
+}
+
+impl Form {
+    /// Decodes form data, replacing `+` with a space and decoding percent-encoded bytes.
+    ///
+    /// Invalid percent-encoding is left as is, and byte sequences which are not valid UTF-8 are
+    /// replaced with `U+FFFD`.
+    pub fn parse(input: &[u8]) -> Form {
+        form_urlencoded::parse(input).into_owned().collect()
+    }
+
+    /// Returns the first value of the field with the given name.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns every value of the field with the given name, in the order they were sent.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.pairs
+            .iter()
+            .filter(move |(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether or not a field with the given name was sent.
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Iterates over every field (name and value), in the order they were sent.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// The number of fields (counting each value of a repeated field separately).
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    /// Whether there are no fields.
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    /// Deserializes the form into a struct (or any other type implementing `Deserialize`).
+    ///
+    /// Fields are converted from strings into numbers, booleans, etc. as needed. Each field must
+    /// only appear once; use [Form::get_all] to read repeated fields.
+    ///
+    /// ```ignore
+    /// #[derive(Deserialize)]
+    /// struct Login {
+    ///     username: String,
+    ///     password: String,
+    ///     remember_me: Option<bool>,
+    /// }
+    ///
+    /// let login = req.take_body().form()?.deserialize::<Login>()?;
+    /// ```
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T, FormError> {
+        let encoded = form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(&self.pairs)
+            .finish();
+        serde_urlencoded::from_str(&encoded).map_err(FormError::Invalid)
+    }
+}
+
+impl IntoIterator for Form {
+    type Item = (String, String);
+    type IntoIter = std::vec::IntoIter<(String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.pairs.into_iter()
+    }
+}
+
+impl FromIterator<(String, String)> for Form {
+    fn from_iter<T: IntoIterator<Item = (String, String)>>(iter: T) -> Self {
+        Form {
+            pairs: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl Body {
+    /// Reads the body to completion and decodes it as `application/x-www-form-urlencoded` data.
+    ///
+    /// The MIME type of the body must be `application/x-www-form-urlencoded`, and the body must be
+    /// no longer than [DEFAULT_FORM_LIMIT] bytes. Use [Body::form_with_limit] to accept larger (or
+    /// only smaller) bodies.
+    pub fn form(self) -> Result<Form, FormError> {
+        self.form_with_limit(DEFAULT_FORM_LIMIT)
+    }
+
+    /// Reads the body to completion and decodes it as `application/x-www-form-urlencoded` data, as
+    /// long as it is no longer than `limit` bytes (see [Body::form]).
+    pub fn form_with_limit(self, limit: usize) -> Result<Form, FormError> {
+        let essence = self.mime().essence();
+        if essence != "application/x-www-form-urlencoded" {
+            return Err(FormError::UnsupportedMediaType(essence.to_string()));
+        }
+
+        let bytes = self
+            .into_bytes_limited(limit)?
+            .ok_or(FormError::TooLarge(limit))?;
+
+        Ok(Form::parse(&bytes))
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+/// An error encountered when reading form data.
+pub enum FormError {
+    #[error("expected a form, but the body is `{0}`")]
+    /// The body does not have the `application/x-www-form-urlencoded` MIME type.
+    UnsupportedMediaType(String),
+    #[error("the body is longer than the limit of {0} bytes")]
+    /// The body is longer than the limit.
+    TooLarge(usize),
+    #[error("failed to read the body")]
+    /// The body could not be read.
+    Io(#[from] io::Error),
+    #[error("the form does not have the expected fields")]
+    /// The form could not be deserialized into the expected type.
+    Invalid(serde_urlencoded::de::Error),
+}
+
+impl FormError {
+    /// The status which should be sent in response to a request whose form could not be read
+    /// because of this error.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            FormError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            FormError::TooLarge(_) => StatusCode::CONTENT_TOO_LARGE,
+            FormError::Io(error) => read_error_status(error),
+            FormError::Invalid(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Deserialize;
+
+    use crate::{
+        body::{
+            mime::{FORM, JSON},
+            Body,
+        },
+        request::Method,
+        response::status::StatusCode,
+        Request,
+    };
+
+    use super::Form;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Search {
+        q: String,
+        page: u32,
+        exact: Option<bool>,
+    }
+
+    #[lunatic::test]
+    fn test_parse() {
+        let form = Form::parse(b"name=J%C3%B6rg+Smith&tag=a&tag=b&empty=&tag=%zz");
+        assert_eq!(form.get("name"), Some("Jörg Smith"));
+        assert_eq!(form.get("empty"), Some(""));
+        assert_eq!(
+            form.get_all("tag").collect::<Vec<_>>(),
+            vec!["a", "b", "%zz"]
+        );
+        assert_eq!(form.get("missing"), None);
+        assert_eq!(form.len(), 5);
+    }
+
+    #[lunatic::test]
+    fn test_body_form() {
+        let search = Body::from_string("q=rust+http&page=2")
+            .with_mime(FORM)
+            .form()
+            .unwrap()
+            .deserialize::<Search>()
+            .unwrap();
+        assert_eq!(
+            search,
+            Search {
+                q: "rust http".to_string(),
+                page: 2,
+                exact: None
+            }
+        );
+
+        let error = Body::from_string("q=rust&page=two")
+            .with_mime(FORM)
+            .form()
+            .unwrap()
+            .deserialize::<Search>()
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+
+        let error = Body::from_string("q=rust")
+            .with_mime(JSON)
+            .form()
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let error = Body::from_reader(&b"q=rust&page=2"[..], None)
+            .with_mime(FORM)
+            .form_with_limit(4)
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::CONTENT_TOO_LARGE);
+    }
+
+    #[lunatic::test]
+    fn test_query() {
+        let req = Request::build("http://example.com/search?q=a%26b&page=3&exact=true")
+            .method(Method::Get)
+            .build();
+        assert_eq!(
+            req.query().deserialize::<Search>().unwrap(),
+            Search {
+                q: "a&b".to_string(),
+                page: 3,
+                exact: Some(true)
+            }
+        );
+    }
+}
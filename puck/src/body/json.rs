@@ -0,0 +1,128 @@
+//! Reading JSON from bodies.
+
+use std::io;
+
+use serde::de::DeserializeOwned;
+
+use crate::response::status::StatusCode;
+
+use super::{read_error_status, Body};
+
+/// The largest body (in bytes) which [Body::json] will read.
+pub const DEFAULT_JSON_LIMIT: usize = 1024 * 1024;
+
+impl Body {
+    /// Reads the body to completion and deserializes it from JSON.
+    ///
+    /// The MIME type of the body must be `application/json` (or another JSON type, such as
+    /// `application/ld+json`), and the body must be no longer than [DEFAULT_JSON_LIMIT] bytes. Use
+    /// [Body::json_with_limit] to accept larger (or only smaller) bodies.
+    ///
+    /// ```ignore
+    /// let item = match req.take_body().json::<Item>() {
+    ///     Ok(item) => item,
+    ///     Err(e) => return stream.respond(Response::error(e.status())).unwrap(),
+    /// };
+    /// ```
+    pub fn json<T: DeserializeOwned>(self) -> Result<T, JsonError> {
+        self.json_with_limit(DEFAULT_JSON_LIMIT)
+    }
+
+    /// Reads the body to completion and deserializes it from JSON, as long as it is no longer than
+    /// `limit` bytes (see [Body::json]).
+    pub fn json_with_limit<T: DeserializeOwned>(self, limit: usize) -> Result<T, JsonError> {
+        let mime = self.mime();
+        if !(mime.essence() == "application/json" || mime.essence().ends_with("+json")) {
+            return Err(JsonError::UnsupportedMediaType(mime.essence().to_string()));
+        }
+        let bytes = self
+            .into_bytes_limited(limit)?
+            .ok_or(JsonError::TooLarge(limit))?;
+
+        serde_json::from_slice(&bytes).map_err(JsonError::Invalid)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+/// An error encountered when reading JSON from a [Body].
+pub enum JsonError {
+    #[error("expected a JSON body, but the body is `{0}`")]
+    /// The body does not have a JSON MIME type.
+    UnsupportedMediaType(String),
+    #[error("the body is longer than the limit of {0} bytes")]
+    /// The body is longer than the limit.
+    TooLarge(usize),
+    #[error("failed to read the body")]
+    /// The body could not be read.
+    Io(#[from] io::Error),
+    #[error("the body is not valid JSON of the expected form")]
+    /// The body is not valid JSON, or does not have the expected structure.
+    Invalid(serde_json::Error),
+}
+
+impl JsonError {
+    /// The status which should be sent in response to a request whose body could not be read
+    /// because of this error.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            JsonError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            JsonError::TooLarge(_) => StatusCode::CONTENT_TOO_LARGE,
+            JsonError::Io(error) => read_error_status(error),
+            JsonError::Invalid(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::{
+        body::{
+            mime::{BYTE_STREAM, JSON},
+            Body,
+        },
+        response::status::StatusCode,
+        Response,
+    };
+
+    use super::JsonError;
+
+    #[lunatic::test]
+    fn test_json() {
+        let value = Body::from_string(r#"{"a": 1, "b": 2}"#)
+            .with_mime(JSON)
+            .json::<HashMap<String, u32>>()
+            .unwrap();
+        assert_eq!(value.get("b"), Some(&2));
+
+        let error = Body::from_string("{}")
+            .with_mime(BYTE_STREAM)
+            .json::<HashMap<String, u32>>()
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let error = Body::from_string(r#"{"a": "one"}"#)
+            .with_mime(JSON)
+            .json::<HashMap<String, u32>>()
+            .unwrap_err();
+        assert!(matches!(error, JsonError::Invalid(_)));
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+
+        let error = Body::from_reader(&b"[1, 2, 3, 4]"[..], None)
+            .with_mime(JSON)
+            .json_with_limit::<Vec<u32>>(8)
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::CONTENT_TOO_LARGE);
+    }
+
+    #[lunatic::test]
+    fn test_json_response() {
+        let mut response = Response::json(&vec![1, 2, 3]).unwrap();
+        assert_eq!(
+            response.headers().get("Content-Type"),
+            Some("application/json")
+        );
+        assert_eq!(response.take_body().into_string().unwrap(), "[1,2,3]");
+    }
+}
@@ -1,10 +1,10 @@
 //! HTTP MIME types.
 
-use std::{borrow::Cow, fmt::Display};
+use std::{borrow::Cow, fmt::Display, str::FromStr};
 
 /* This code comes from https://github.com/http-rs/http-types/blob/main/src/mime/parse.rs */
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 /// The MIME type of a request.
 ///
 /// See [MDN](https://developer.mozilla.org/en-US/docs/Web/HTTP/Basics_of_HTTP/MIME_types) for an
@@ -42,6 +42,7 @@ impl Display for ParamValue {
     }
 }
 
+/// `text/html`
 pub const HTML: Mime = Mime {
     essence: Cow::Borrowed("text/html"),
     basetype: Cow::Borrowed("text"),
@@ -50,6 +51,7 @@ pub const HTML: Mime = Mime {
     params: vec![],
 };
 
+/// `text/plain`
 pub const PLAIN: Mime = Mime {
     essence: Cow::Borrowed("text/plain"),
     basetype: Cow::Borrowed("text"),
@@ -58,6 +60,25 @@ pub const PLAIN: Mime = Mime {
     params: vec![],
 };
 
+/// `application/json`
+pub const JSON: Mime = Mime {
+    essence: Cow::Borrowed("application/json"),
+    basetype: Cow::Borrowed("application"),
+    subtype: Cow::Borrowed("json"),
+    is_utf8: false,
+    params: vec![],
+};
+
+/// `application/x-www-form-urlencoded`
+pub const FORM: Mime = Mime {
+    essence: Cow::Borrowed("application/x-www-form-urlencoded"),
+    basetype: Cow::Borrowed("application"),
+    subtype: Cow::Borrowed("x-www-form-urlencoded"),
+    is_utf8: false,
+    params: vec![],
+};
+
+/// `application/octet-stream`, for arbitrary binary data.
 pub const BYTE_STREAM: Mime = Mime {
     essence: Cow::Borrowed("application/octet-stream"),
     basetype: Cow::Borrowed("application"),
@@ -66,6 +87,350 @@ pub const BYTE_STREAM: Mime = Mime {
     params: vec![],
 };
 
+/// `text/css`
+pub const CSS: Mime = Mime {
+    essence: Cow::Borrowed("text/css"),
+    basetype: Cow::Borrowed("text"),
+    subtype: Cow::Borrowed("css"),
+    is_utf8: true,
+    params: vec![],
+};
+
+/// `text/javascript`
+pub const JAVASCRIPT: Mime = Mime {
+    essence: Cow::Borrowed("text/javascript"),
+    basetype: Cow::Borrowed("text"),
+    subtype: Cow::Borrowed("javascript"),
+    is_utf8: true,
+    params: vec![],
+};
+
+/// `text/csv`
+pub const CSV: Mime = Mime {
+    essence: Cow::Borrowed("text/csv"),
+    basetype: Cow::Borrowed("text"),
+    subtype: Cow::Borrowed("csv"),
+    is_utf8: true,
+    params: vec![],
+};
+
+/// `application/xml`
+pub const XML: Mime = Mime {
+    essence: Cow::Borrowed("application/xml"),
+    basetype: Cow::Borrowed("application"),
+    subtype: Cow::Borrowed("xml"),
+    is_utf8: false,
+    params: vec![],
+};
+
+/// `application/pdf`
+pub const PDF: Mime = Mime {
+    essence: Cow::Borrowed("application/pdf"),
+    basetype: Cow::Borrowed("application"),
+    subtype: Cow::Borrowed("pdf"),
+    is_utf8: false,
+    params: vec![],
+};
+
+/// `application/wasm`
+pub const WASM: Mime = Mime {
+    essence: Cow::Borrowed("application/wasm"),
+    basetype: Cow::Borrowed("application"),
+    subtype: Cow::Borrowed("wasm"),
+    is_utf8: false,
+    params: vec![],
+};
+
+/// `multipart/form-data`
+///
+/// Note that a `boundary` parameter is needed for this to be used as the `Content-Type` of a body.
+pub const MULTIPART_FORM: Mime = Mime {
+    essence: Cow::Borrowed("multipart/form-data"),
+    basetype: Cow::Borrowed("multipart"),
+    subtype: Cow::Borrowed("form-data"),
+    is_utf8: false,
+    params: vec![],
+};
+
+/// `image/svg+xml`
+pub const SVG: Mime = Mime {
+    essence: Cow::Borrowed("image/svg+xml"),
+    basetype: Cow::Borrowed("image"),
+    subtype: Cow::Borrowed("svg+xml"),
+    is_utf8: false,
+    params: vec![],
+};
+
+/// `image/png`
+pub const PNG: Mime = Mime {
+    essence: Cow::Borrowed("image/png"),
+    basetype: Cow::Borrowed("image"),
+    subtype: Cow::Borrowed("png"),
+    is_utf8: false,
+    params: vec![],
+};
+
+/// `image/jpeg`
+pub const JPEG: Mime = Mime {
+    essence: Cow::Borrowed("image/jpeg"),
+    basetype: Cow::Borrowed("image"),
+    subtype: Cow::Borrowed("jpeg"),
+    is_utf8: false,
+    params: vec![],
+};
+
+/// `image/gif`
+pub const GIF: Mime = Mime {
+    essence: Cow::Borrowed("image/gif"),
+    basetype: Cow::Borrowed("image"),
+    subtype: Cow::Borrowed("gif"),
+    is_utf8: false,
+    params: vec![],
+};
+
+/// `image/webp`
+pub const WEBP: Mime = Mime {
+    essence: Cow::Borrowed("image/webp"),
+    basetype: Cow::Borrowed("image"),
+    subtype: Cow::Borrowed("webp"),
+    is_utf8: false,
+    params: vec![],
+};
+
+/// `image/vnd.microsoft.icon`
+pub const ICO: Mime = Mime {
+    essence: Cow::Borrowed("image/vnd.microsoft.icon"),
+    basetype: Cow::Borrowed("image"),
+    subtype: Cow::Borrowed("vnd.microsoft.icon"),
+    is_utf8: false,
+    params: vec![],
+};
+
+/// `font/woff`
+pub const WOFF: Mime = Mime {
+    essence: Cow::Borrowed("font/woff"),
+    basetype: Cow::Borrowed("font"),
+    subtype: Cow::Borrowed("woff"),
+    is_utf8: false,
+    params: vec![],
+};
+
+/// `font/woff2`
+pub const WOFF2: Mime = Mime {
+    essence: Cow::Borrowed("font/woff2"),
+    basetype: Cow::Borrowed("font"),
+    subtype: Cow::Borrowed("woff2"),
+    is_utf8: false,
+    params: vec![],
+};
+
+/// `video/mp4`
+pub const MP4: Mime = Mime {
+    essence: Cow::Borrowed("video/mp4"),
+    basetype: Cow::Borrowed("video"),
+    subtype: Cow::Borrowed("mp4"),
+    is_utf8: false,
+    params: vec![],
+};
+
+/// `video/webm`
+pub const WEBM: Mime = Mime {
+    essence: Cow::Borrowed("video/webm"),
+    basetype: Cow::Borrowed("video"),
+    subtype: Cow::Borrowed("webm"),
+    is_utf8: false,
+    params: vec![],
+};
+
+/// `audio/mpeg`
+pub const MP3: Mime = Mime {
+    essence: Cow::Borrowed("audio/mpeg"),
+    basetype: Cow::Borrowed("audio"),
+    subtype: Cow::Borrowed("mpeg"),
+    is_utf8: false,
+    params: vec![],
+};
+
+impl Mime {
+    /// The type and subtype, without any parameters (e.g. `text/html`).
+    pub fn essence(&self) -> &str {
+        &self.essence
+    }
+
+    /// The value of the parameter with the given name (e.g. the `boundary` of a
+    /// `multipart/form-data` type). Parameter names are case-insensitive.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        if self.is_utf8 && name.eq_ignore_ascii_case("charset") {
+            return Some("utf-8");
+        }
+        self.params
+            .iter()
+            .find(|(key, _)| key.0.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.0.as_ref())
+    }
+
+    /// The type (e.g. `text` for `text/html`).
+    pub fn basetype(&self) -> &str {
+        &self.basetype
+    }
+
+    /// The subtype (e.g. `html` for `text/html`).
+    pub fn subtype(&self) -> &str {
+        &self.subtype
+    }
+
+    /// Guess the MIME type of a file from its extension (without the leading `.`, e.g. `"css"`).
+    ///
+    /// This is how the `Content-Type` of files served from disk is chosen. Returns `None` for
+    /// extensions which are not recognised.
+    pub fn from_extension(extension: &str) -> Option<Mime> {
+        let mime = match extension.to_ascii_lowercase().as_str() {
+            "html" | "htm" => HTML,
+            "txt" | "text" => PLAIN,
+            "css" => CSS,
+            "js" | "mjs" => JAVASCRIPT,
+            "json" | "map" => JSON,
+            "csv" => CSV,
+            "xml" => XML,
+            "pdf" => PDF,
+            "wasm" => WASM,
+            "svg" => SVG,
+            "png" => PNG,
+            "jpg" | "jpeg" => JPEG,
+            "gif" => GIF,
+            "webp" => WEBP,
+            "ico" => ICO,
+            "woff" => WOFF,
+            "woff2" => WOFF2,
+            "mp4" => MP4,
+            "webm" => WEBM,
+            "mp3" => MP3,
+            _ => return None,
+        };
+        Some(mime)
+    }
+
+    /// Guess the MIME type of a file from the extension of its path, falling back to
+    /// [BYTE_STREAM] if the extension is not recognised (see [Mime::from_extension]).
+    pub fn guess_from_path(path: impl AsRef<std::path::Path>) -> Mime {
+        path.as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(Mime::from_extension)
+            .unwrap_or(BYTE_STREAM)
+    }
+}
+
+impl FromStr for Mime {
+    type Err = InvalidMime;
+
+    /// Parses a MIME type (e.g. the value of a `Content-Type` header), along with its
+    /// parameters.
+    ///
+    /// The type, subtype and parameter names are converted to lower case. Parameter values may be
+    /// quoted strings; if a parameter appears more than once, the first value is used.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidMime(value.to_string());
+
+        let (essence, params) = match value.split_once(';') {
+            Some((essence, params)) => (essence, params),
+            None => (value, ""),
+        };
+        let essence = essence.trim().to_ascii_lowercase();
+        let (basetype, subtype) = essence.split_once('/').ok_or_else(invalid)?;
+        if basetype.is_empty()
+            || subtype.is_empty()
+            || !basetype.chars().all(is_http_token_code_point)
+            || !subtype.chars().all(is_http_token_code_point)
+        {
+            return Err(invalid());
+        }
+
+        let mut is_utf8 = false;
+        let mut parsed: Vec<(ParamName, ParamValue)> = Vec::new();
+        for (name, value) in parse_params(params) {
+            if parsed.iter().any(|(existing, _)| existing.0 == name) {
+                continue;
+            }
+            if name == "charset" && value.eq_ignore_ascii_case("utf-8") {
+                is_utf8 = true;
+                continue;
+            }
+            parsed.push((ParamName(Cow::Owned(name)), ParamValue(Cow::Owned(value))));
+        }
+
+        Ok(Mime {
+            basetype: Cow::Owned(basetype.to_string()),
+            subtype: Cow::Owned(subtype.to_string()),
+            essence: Cow::Owned(essence),
+            is_utf8,
+            params: parsed,
+        })
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[error("`{0}` is not a valid MIME type")]
+/// An error encountered when parsing a [Mime] from a string which is not a valid MIME type.
+pub struct InvalidMime(String);
+
+/// Parses a list of `;`-separated parameters (as found after the type in a `Content-Type` header,
+/// or after the disposition in a `Content-Disposition` header), whose values may be quoted.
+///
+/// Parameter names are converted to lower case. Parameters which are not of the form
+/// `name=value` are skipped.
+pub(crate) fn parse_params(input: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    loop {
+        // skip to the start of the next parameter
+        while let Some(c) = chars.peek() {
+            if *c == ';' || c.is_whitespace() {
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut name = String::new();
+        while let Some(c) = chars.next_if(|c| *c != '=' && *c != ';') {
+            name.push(c);
+        }
+        if chars.next_if_eq(&'=').is_none() {
+            continue;
+        }
+
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => value.extend(chars.next()),
+                    c => value.push(c),
+                }
+            }
+            // skip anything between the closing quote and the next parameter
+            while chars.next_if(|c| *c != ';').is_some() {}
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ';') {
+                value.push(c);
+            }
+            value.truncate(value.trim_end().len());
+        }
+
+        let name = name.trim().to_ascii_lowercase();
+        if !name.is_empty() && name.chars().all(is_http_token_code_point) {
+            params.push((name, value));
+        }
+    }
+
+    params
+}
+
 /// Implementation of the
 /// [WHATWG MIME serialization algorithm](https://mimesniff.spec.whatwg.org/#serializing-a-mime-type)
 pub(crate) fn format(mime_type: &Mime, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -166,3 +531,44 @@ fn is_http_token_code_point(c: char) -> bool {
 }
 
 /* End "borrowed" code section. */
+
+#[cfg(test)]
+mod test {
+    use super::{Mime, CSS, JSON, MULTIPART_FORM, PNG};
+
+    #[lunatic::test]
+    fn test_parse() {
+        let mime = "Multipart/Form-Data; Boundary=\"a \\\"quoted\\\" boundary\"; charset=UTF-8; boundary=second"
+            .parse::<Mime>()
+            .unwrap();
+        assert_eq!(mime.essence(), "multipart/form-data");
+        assert_eq!(mime.basetype(), "multipart");
+        assert_eq!(mime.subtype(), "form-data");
+        assert_eq!(mime.param("boundary"), Some("a \"quoted\" boundary"));
+        assert_eq!(mime.param("CHARSET"), Some("utf-8"));
+        assert_eq!(
+            mime.to_string(),
+            "multipart/form-data;charset=utf-8;boundary=\"a \\\"quoted\\\" boundary\""
+        );
+
+        assert_eq!("application/json".parse::<Mime>().unwrap(), JSON);
+        assert_eq!(
+            "multipart/form-data".parse::<Mime>().unwrap(),
+            MULTIPART_FORM
+        );
+        assert!("text".parse::<Mime>().is_err());
+        assert!("text/".parse::<Mime>().is_err());
+        assert!("te xt/html".parse::<Mime>().is_err());
+    }
+
+    #[lunatic::test]
+    fn test_guess() {
+        assert_eq!(Mime::from_extension("CSS"), Some(CSS));
+        assert_eq!(Mime::from_extension("unknown"), None);
+        assert_eq!(Mime::guess_from_path("static/logo.png"), PNG);
+        assert_eq!(
+            Mime::guess_from_path("Makefile").essence(),
+            "application/octet-stream"
+        );
+    }
+}
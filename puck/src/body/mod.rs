@@ -1,10 +1,16 @@
 //! HTTP bodies.
 
 use std::fmt;
-use std::io::{BufRead, Cursor, Read};
+use std::io::{self, BufRead, Cursor, Read};
+
+use crate::{request::RequestParseError, response::status::StatusCode};
 
 use self::mime::{Mime, BYTE_STREAM};
 
+pub mod chunked;
+pub mod form;
+pub mod json;
+pub mod multipart;
 // for now, todo: add documentation
 #[allow(missing_docs)]
 pub mod mime;
@@ -42,6 +48,9 @@ impl Body {
 
     /// Construct a new `Body` from the provided reader (which should implement `BufRead`). Note
     /// that if you can, you should ideally supply `content_length`.
+    ///
+    /// When a `Body` without a `content_length` is sent in a response, it is sent using the
+    /// `chunked` transfer coding.
     pub fn from_reader(reader: impl BufRead + 'static, content_length: Option<usize>) -> Self {
         Self {
             reader: Box::new(reader),
@@ -66,6 +75,20 @@ impl Body {
         }
     }
 
+    /// Set the MIME type of the contents of this `Body`.
+    pub fn with_mime(mut self, mime: Mime) -> Self {
+        self.mime = mime;
+        self
+    }
+
+    /// The MIME type of the contents of this `Body`.
+    ///
+    /// For the body of a request which was received, this is the type named in the request's
+    /// `Content-Type` header (or `application/octet-stream` if it did not have one).
+    pub fn mime(&self) -> &Mime {
+        &self.mime
+    }
+
     /// Reads to completion from the underlying IO source, and returns the result as bytes
     /// (`Vec<u8>`).
     pub fn into_bytes(mut self) -> std::io::Result<Vec<u8>> {
@@ -74,6 +97,23 @@ impl Body {
         Ok(buf)
     }
 
+    /// Reads to completion from the underlying IO source, as long as there are no more than
+    /// `limit` bytes to read. Returns `None` (having read `limit + 1` bytes) if there are more.
+    pub(crate) fn into_bytes_limited(self, limit: usize) -> std::io::Result<Option<Vec<u8>>> {
+        if self.length.map_or(false, |length| length > limit) {
+            return Ok(None);
+        }
+
+        let mut bytes = Vec::with_capacity(self.length.unwrap_or(0));
+        // read one byte more than the limit, to find out whether the body is too long
+        self.take(limit as u64 + 1).read_to_end(&mut bytes)?;
+        Ok(if bytes.len() > limit {
+            None
+        } else {
+            Some(bytes)
+        })
+    }
+
     /// Reads to completion from the underlying IO source, and returns the result as a
     /// `String`.
     pub fn into_string(mut self) -> std::io::Result<String> {
@@ -95,6 +135,59 @@ impl From<&str> for Body {
     }
 }
 
+/// Cuts off a body (of unknown length) once it is longer than the limit. Reads past the limit
+/// fail with [RequestParseError::BodyTooLarge], so they are answered with `413 Content Too Large`
+/// (see [read_error_status]).
+pub(crate) struct Limited<R> {
+    reader: R,
+    remaining: usize,
+    /// Once the body has gone over the limit, every read fails (so that the rest of the body is
+    /// not mistaken for the next request).
+    exceeded: bool,
+}
+
+impl<R> Limited<R> {
+    pub(crate) fn new(reader: R, limit: usize) -> Self {
+        Self {
+            reader,
+            remaining: limit,
+            exceeded: false,
+        }
+    }
+}
+
+impl<R: Read> Read for Limited<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.exceeded {
+            // one more byte than is allowed is read, to tell whether the body goes over the limit
+            let max = buf.len().min(self.remaining.saturating_add(1));
+            let read = self.reader.read(&mut buf[..max])?;
+            if read <= self.remaining {
+                self.remaining -= read;
+                return Ok(read);
+            }
+            self.exceeded = true;
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            RequestParseError::BodyTooLarge,
+        ))
+    }
+}
+
+/// The status which should be sent in response to a request whose body could not be read because
+/// of `error`.
+///
+/// This is `400 Bad Request`, unless the body was cut off for exceeding the server's limit (see
+/// [ServerConfig::max_body_size](crate::core::ServerConfig::max_body_size)), or the client took
+/// too long to send it (see [ServerConfig::body_timeout](crate::core::ServerConfig::body_timeout)).
+pub(crate) fn read_error_status(error: &io::Error) -> StatusCode {
+    error
+        .get_ref()
+        .and_then(|error| error.downcast_ref::<RequestParseError>())
+        .map_or(StatusCode::BAD_REQUEST, RequestParseError::status)
+}
+
 impl Read for Body {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         let buf = match self.length {
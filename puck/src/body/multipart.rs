@@ -0,0 +1,571 @@
+//! A streaming parser for `multipart/form-data` bodies, which is how HTML forms containing file
+//! uploads are submitted.
+//!
+//! See [RFC 7578](https://www.rfc-editor.org/rfc/rfc7578).
+
+use std::io::{self, Read, Write};
+
+use crate::{headers::HeaderMap, response::status::StatusCode};
+
+use super::{
+    mime::{parse_params, Mime},
+    read_error_status, Body,
+};
+
+/// The largest part (in bytes, not including its headers) which will be read by default.
+pub const DEFAULT_MAX_PART_SIZE: usize = 10 * 1024 * 1024;
+
+/// The largest body (in bytes) which will be read by default.
+pub const DEFAULT_MAX_TOTAL_SIZE: usize = 50 * 1024 * 1024;
+
+/// The longest block of headers (in bytes) a part may have by default.
+pub const DEFAULT_MAX_HEADERS_SIZE: usize = 8 * 1024;
+
+/// The maximum number of headers a part may have.
+const MAX_PART_HEADERS: usize = 16;
+
+/// How much is read from the body at once.
+const READ_SIZE: usize = 8 * 1024;
+
+/// Reads the parts of a `multipart/form-data` body one at a time, without buffering them in
+/// memory.
+///
+/// ```ignore
+/// let mut multipart = req.take_body().multipart()?.max_part_size(100 * 1024 * 1024);
+/// while let Some(mut part) = multipart.next_part()? {
+///     match part.name() {
+///         Some("upload") => {
+///             let mut file = File::create("upload")?;
+///             part.copy_to(&mut file)?;
+///         }
+///         Some("title") => title = Some(part.text()?),
+///         _ => {}
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Multipart {
+    body: Body,
+    /// The delimiter which precedes each part (`\r\n--` followed by the boundary).
+    delimiter: Vec<u8>,
+    /// Data which has been read from the body, but not yet consumed.
+    buf: Vec<u8>,
+    eof: bool,
+    state: State,
+    /// The number of bytes read from the body.
+    total_size: usize,
+    /// The number of bytes read from the current part.
+    part_size: usize,
+    max_part_size: usize,
+    max_total_size: usize,
+    max_headers_size: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Before the first delimiter.
+    Preamble,
+    /// Part-way through the data of a part.
+    Data,
+    /// At the delimiter which follows a part.
+    Delimiter,
+    /// The closing delimiter has been read.
+    Done,
+}
+
+impl Body {
+    /// Reads the body as `multipart/form-data`.
+    ///
+    /// The MIME type of the body must be `multipart/form-data`, with a `boundary` parameter. The
+    /// body itself is only read as the parts are (see [Multipart::next_part]).
+    pub fn multipart(self) -> Result<Multipart, MultipartError> {
+        if self.mime().essence() != "multipart/form-data" {
+            return Err(MultipartError::UnsupportedMediaType(
+                self.mime().essence().to_string(),
+            ));
+        }
+        let boundary = match self.mime().param("boundary") {
+            Some(boundary) if !boundary.is_empty() && boundary.len() <= 70 => boundary,
+            _ => return Err(MultipartError::MissingBoundary),
+        };
+        let delimiter = [b"\r\n--", boundary.as_bytes()].concat();
+
+        Ok(Multipart {
+            body: self,
+            delimiter,
+            // the first delimiter is not preceded by a line break (unless there is a preamble), so
+            // one is added to find it in the same way as the others
+            buf: b"\r\n".to_vec(),
+            eof: false,
+            state: State::Preamble,
+            total_size: 0,
+            part_size: 0,
+            max_part_size: DEFAULT_MAX_PART_SIZE,
+            max_total_size: DEFAULT_MAX_TOTAL_SIZE,
+            max_headers_size: DEFAULT_MAX_HEADERS_SIZE,
+        })
+    }
+}
+
+impl Multipart {
+    /// Set the largest part (in bytes, not including its headers) which will be read. This is
+    /// [DEFAULT_MAX_PART_SIZE] by default.
+    pub fn max_part_size(mut self, max_part_size: usize) -> Self {
+        self.max_part_size = max_part_size;
+        self
+    }
+
+    /// Set the largest body (in bytes) which will be read. This is [DEFAULT_MAX_TOTAL_SIZE] by
+    /// default.
+    pub fn max_total_size(mut self, max_total_size: usize) -> Self {
+        self.max_total_size = max_total_size;
+        self
+    }
+
+    /// Set the longest block of headers (in bytes) which a part may have. This is
+    /// [DEFAULT_MAX_HEADERS_SIZE] by default.
+    pub fn max_headers_size(mut self, max_headers_size: usize) -> Self {
+        self.max_headers_size = max_headers_size;
+        self
+    }
+
+    /// Returns the next part of the body, or `None` once every part has been read.
+    ///
+    /// Anything left unread of the previous part is skipped.
+    pub fn next_part(&mut self) -> Result<Option<Part<'_>>, MultipartError> {
+        let mut scratch = [0; 1024];
+        while matches!(self.state, State::Preamble | State::Data) {
+            self.read_data(&mut scratch)?;
+        }
+        if self.state == State::Done {
+            return Ok(None);
+        }
+
+        self.fill(self.delimiter.len() + 2)?;
+        self.buf.drain(..self.delimiter.len());
+        if self.buf.starts_with(b"--") {
+            // anything after the closing delimiter (the "epilogue") is ignored
+            self.state = State::Done;
+            return Ok(None);
+        }
+
+        // the delimiter may be followed by whitespace before the line break
+        loop {
+            self.fill(2)?;
+            match self.buf.first() {
+                Some(b' ') | Some(b'\t') => {
+                    self.buf.remove(0);
+                }
+                _ => break,
+            }
+        }
+        if !self.buf.starts_with(b"\r\n") {
+            return Err(MultipartError::Malformed(
+                "expected a line break after the boundary",
+            ));
+        }
+        self.buf.drain(..2);
+
+        let headers = self.read_headers()?;
+        let disposition = headers
+            .get("content-disposition")
+            .map(|value| match value.split_once(';') {
+                Some((_, params)) => parse_params(params),
+                None => vec![],
+            })
+            .unwrap_or_default();
+        let param = |name: &str| {
+            disposition
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+        let name = param("name");
+        let filename = param("filename");
+        let content_type = headers
+            .get("content-type")
+            .and_then(|content_type| content_type.parse::<Mime>().ok());
+
+        self.state = State::Data;
+        self.part_size = 0;
+
+        Ok(Some(Part {
+            multipart: self,
+            headers,
+            name,
+            filename,
+            content_type,
+        }))
+    }
+
+    /// Reads the headers of a part, including the blank line after them.
+    fn read_headers(&mut self) -> Result<HeaderMap, MultipartError> {
+        self.fill(2)?;
+        if self.buf.starts_with(b"\r\n") {
+            self.buf.drain(..2);
+            return Ok(HeaderMap::new());
+        }
+
+        let end = loop {
+            if let Some(end) = find(&self.buf, b"\r\n\r\n") {
+                break end + 4;
+            }
+            if self.buf.len() > self.max_headers_size {
+                return Err(MultipartError::HeadersTooLarge(self.max_headers_size));
+            }
+            if self.eof {
+                return Err(MultipartError::Malformed(
+                    "the body ended part-way through a part",
+                ));
+            }
+            self.fill(self.buf.len() + 1)?;
+        };
+        if end > self.max_headers_size {
+            return Err(MultipartError::HeadersTooLarge(self.max_headers_size));
+        }
+
+        let mut parsed = [httparse::EMPTY_HEADER; MAX_PART_HEADERS];
+        let mut headers = HeaderMap::new();
+        match httparse::parse_headers(&self.buf[..end], &mut parsed) {
+            Ok(httparse::Status::Complete((_, parsed))) => {
+                for header in parsed {
+                    let value = std::str::from_utf8(header.value)
+                        .map_err(|_| MultipartError::Malformed("a header is not valid UTF-8"))?;
+                    headers.append_unchecked(header.name.to_string(), value.to_string());
+                }
+            }
+            _ => {
+                return Err(MultipartError::Malformed(
+                    "the headers of a part are not valid",
+                ))
+            }
+        }
+        self.buf.drain(..end);
+
+        Ok(headers)
+    }
+
+    /// Reads data from the current part (or the preamble) into `out`. Once the delimiter which
+    /// ends it is reached, this moves to [State::Delimiter] and returns `0`.
+    fn read_data(&mut self, out: &mut [u8]) -> Result<usize, MultipartError> {
+        if !matches!(self.state, State::Preamble | State::Data) || out.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            self.fill(self.delimiter.len() + 2 + out.len().min(READ_SIZE))?;
+
+            let available = match self.find_delimiter() {
+                Some(0) => {
+                    self.state = State::Delimiter;
+                    return Ok(0);
+                }
+                Some(delimiter) => delimiter,
+                None if self.eof => {
+                    return Err(MultipartError::Malformed(
+                        "the body ended before the closing boundary",
+                    ))
+                }
+                // the end of the buffer could be the start of a delimiter
+                None => self.buf.len().saturating_sub(self.delimiter.len() - 1),
+            };
+            if available == 0 {
+                continue;
+            }
+
+            let read = available.min(out.len());
+            out[..read].copy_from_slice(&self.buf[..read]);
+            self.buf.drain(..read);
+
+            if self.state == State::Data {
+                self.part_size += read;
+                if self.part_size > self.max_part_size {
+                    return Err(MultipartError::PartTooLarge(self.max_part_size));
+                }
+            }
+            return Ok(read);
+        }
+    }
+
+    /// Returns the index of the first delimiter in the buffer.
+    ///
+    /// The boundary may appear in the data of a part, as long as it is not followed by `--`,
+    /// whitespace or a line break (which is how a delimiter is told apart). If there is not yet
+    /// enough in the buffer to tell, the match is assumed to be a delimiter.
+    fn find_delimiter(&self) -> Option<usize> {
+        let mut start = 0;
+        while let Some(found) = find(&self.buf[start..], &self.delimiter) {
+            let index = start + found;
+            let end = index + self.delimiter.len();
+            match self.buf.get(end..end + 2) {
+                Some(b"--") | Some(b"\r\n") | Some([b' ', _]) | Some([b'\t', _]) | None => {
+                    return Some(index)
+                }
+                Some(_) => start = index + 1,
+            }
+        }
+        None
+    }
+
+    /// Reads from the body until there are at least `size` bytes in the buffer, or the body ends.
+    fn fill(&mut self, size: usize) -> Result<(), MultipartError> {
+        let mut chunk = [0; READ_SIZE];
+        while self.buf.len() < size && !self.eof {
+            let read = self.body.read(&mut chunk)?;
+            if read == 0 {
+                self.eof = true;
+            }
+            self.total_size += read;
+            if self.total_size > self.max_total_size {
+                return Err(MultipartError::TooLarge(self.max_total_size));
+            }
+            self.buf.extend_from_slice(&chunk[..read]);
+        }
+        Ok(())
+    }
+}
+
+/// Returns the index at which `needle` first appears in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// A single part of a `multipart/form-data` body (usually either the value of a form field, or an
+/// uploaded file).
+///
+/// The contents of the part can be read using `Read`.
+#[derive(Debug)]
+pub struct Part<'a> {
+    multipart: &'a mut Multipart,
+    headers: HeaderMap,
+    name: Option<String>,
+    filename: Option<String>,
+    content_type: Option<Mime>,
+}
+
+impl<'a> Part<'a> {
+    /// The headers sent with this part.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// The name of the form field this part contains the value of.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The name of the file this part contains, as given by the client (if the part is an
+    /// uploaded file).
+    ///
+    /// This should never be used as a path without sanitising it first.
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    /// The MIME type of this part, if it was given.
+    pub fn content_type(&self) -> Option<&Mime> {
+        self.content_type.as_ref()
+    }
+
+    /// Copies the rest of this part to `writer` (e.g. a file), returning the number of bytes
+    /// copied.
+    pub fn copy_to(&mut self, writer: &mut impl Write) -> Result<u64, MultipartError> {
+        let mut buf = [0; READ_SIZE];
+        let mut copied = 0;
+        loop {
+            let read = self.multipart.read_data(&mut buf)?;
+            if read == 0 {
+                return Ok(copied);
+            }
+            writer.write_all(&buf[..read])?;
+            copied += read as u64;
+        }
+    }
+
+    /// Reads the rest of this part into memory.
+    pub fn bytes(mut self) -> Result<Vec<u8>, MultipartError> {
+        let mut bytes = Vec::new();
+        self.copy_to(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Reads the rest of this part into memory, as a string.
+    pub fn text(self) -> Result<String, MultipartError> {
+        String::from_utf8(self.bytes()?)
+            .map_err(|_| MultipartError::Malformed("a part is not valid UTF-8"))
+    }
+}
+
+impl<'a> Read for Part<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.multipart.read_data(buf).map_err(|error| match error {
+            MultipartError::Io(error) => error,
+            error => io::Error::new(io::ErrorKind::InvalidData, error),
+        })
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+/// An error encountered when reading a `multipart/form-data` body.
+pub enum MultipartError {
+    #[error("expected multipart/form-data, but the body is `{0}`")]
+    /// The body does not have the `multipart/form-data` MIME type.
+    UnsupportedMediaType(String),
+    #[error("the body's MIME type does not have a valid `boundary` parameter")]
+    /// The MIME type of the body does not specify a (valid) boundary between parts.
+    MissingBoundary,
+    #[error("the body is longer than the limit of {0} bytes")]
+    /// The body is longer than the limit.
+    TooLarge(usize),
+    #[error("a part is longer than the limit of {0} bytes")]
+    /// A part is longer than the limit.
+    PartTooLarge(usize),
+    #[error("the headers of a part are longer than the limit of {0} bytes")]
+    /// The headers of a part are longer than the limit.
+    HeadersTooLarge(usize),
+    #[error("the body is not valid multipart/form-data: {0}")]
+    /// The body is not valid `multipart/form-data`.
+    Malformed(&'static str),
+    #[error("failed to read the body")]
+    /// The body could not be read, or a part could not be written out.
+    Io(#[from] io::Error),
+}
+
+impl MultipartError {
+    /// The status which should be sent in response to a request whose body could not be read
+    /// because of this error.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            MultipartError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            MultipartError::TooLarge(_)
+            | MultipartError::PartTooLarge(_)
+            | MultipartError::HeadersTooLarge(_) => StatusCode::CONTENT_TOO_LARGE,
+            MultipartError::Io(error) => read_error_status(error),
+            MultipartError::MissingBoundary | MultipartError::Malformed(_) => {
+                StatusCode::BAD_REQUEST
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use crate::{
+        body::{
+            mime::{Mime, MULTIPART_FORM},
+            Body,
+        },
+        response::status::StatusCode,
+    };
+
+    use super::Multipart;
+
+    const BODY: &str = "preamble\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        Holiday\r\n\
+        --XyZ \r\n\
+        Content-Disposition: form-data; name=\"photo\"; filename=\"beach \\\"1\\\".jpg\"\r\n\
+        Content-Type: image/jpeg\r\n\
+        \r\n\
+        \r\n--XyZZ not the boundary\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"skipped\"\r\n\
+        \r\n\
+        never read\r\n\
+        --XyZ--\r\n\
+        epilogue";
+
+    /// A reader which only returns a few bytes at a time, so that boundaries are split across
+    /// reads.
+    struct Trickle(&'static [u8]);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(3);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    fn parse(body: &'static str) -> Multipart {
+        Body::from_reader(std::io::BufReader::new(Trickle(body.as_bytes())), None)
+            .with_mime(
+                "multipart/form-data; boundary=\"XyZ\""
+                    .parse::<Mime>()
+                    .unwrap(),
+            )
+            .multipart()
+            .unwrap()
+    }
+
+    #[lunatic::test]
+    fn test_parts() {
+        let mut multipart = parse(BODY);
+
+        let part = multipart.next_part().unwrap().unwrap();
+        assert_eq!(part.name(), Some("title"));
+        assert_eq!(part.filename(), None);
+        assert_eq!(part.text().unwrap(), "Holiday");
+
+        let mut part = multipart.next_part().unwrap().unwrap();
+        assert_eq!(part.name(), Some("photo"));
+        assert_eq!(part.filename(), Some("beach \"1\".jpg"));
+        assert_eq!(part.content_type().unwrap().essence(), "image/jpeg");
+        assert_eq!(part.headers().len(), 2);
+        let mut file = Vec::new();
+        part.copy_to(&mut file).unwrap();
+        assert_eq!(file, b"\r\n--XyZZ not the boundary");
+
+        let part = multipart.next_part().unwrap().unwrap();
+        assert_eq!(part.name(), Some("skipped"));
+
+        assert!(multipart.next_part().unwrap().is_none());
+        assert!(multipart.next_part().unwrap().is_none());
+    }
+
+    #[lunatic::test]
+    fn test_boundary_in_data() {
+        let mut multipart = parse(
+            "--XyZ\r\n\r\n\
+            --XyZ at the start,\r\n--XyZ-ish and\r\n--XyZXyZ further on\r\n\
+            --XyZ--\r\n",
+        );
+
+        let part = multipart.next_part().unwrap().unwrap();
+        assert_eq!(
+            part.text().unwrap(),
+            "--XyZ at the start,\r\n--XyZ-ish and\r\n--XyZXyZ further on"
+        );
+        assert!(multipart.next_part().unwrap().is_none());
+    }
+
+    #[lunatic::test]
+    fn test_limits() {
+        let mut multipart = parse(BODY).max_part_size(4);
+        let error = multipart.next_part().unwrap().unwrap().text().unwrap_err();
+        assert_eq!(error.status(), StatusCode::CONTENT_TOO_LARGE);
+
+        let error = parse(BODY).max_total_size(40).next_part().unwrap_err();
+        assert_eq!(error.status(), StatusCode::CONTENT_TOO_LARGE);
+    }
+
+    #[lunatic::test]
+    fn test_malformed() {
+        let mut multipart = parse("--XyZ\r\n\r\nno closing boundary");
+        let error = multipart.next_part().unwrap().unwrap().text().unwrap_err();
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+
+        let error = Body::from_string("")
+            .with_mime(MULTIPART_FORM)
+            .multipart()
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,459 @@
+//! Compressing responses (and decompressing request bodies) using the content codings the client
+//! supports.
+//!
+//! Compression is opt-in: wrap a router (or a single route) in the [Compress] middleware.
+//!
+//! ```ignore
+//! let router = Router::new()
+//!     .get(Match::new().at(path("")), index)
+//!     .wrap(Compress::new());
+//! ```
+
+use std::io::{BufReader, Read};
+
+use flate2::{
+    read::{GzEncoder, MultiGzDecoder, ZlibDecoder, ZlibEncoder},
+    Compression,
+};
+
+use crate::{
+    body::{mime::Mime, Body, Limited},
+    core::{
+        config::DEFAULT_MAX_BODY_SIZE,
+        router::{Handler, Middleware},
+        Stream, UsedStream,
+    },
+    response::status::StatusCode,
+    Request, Response,
+};
+
+/// Bodies shorter than this (in bytes) are not compressed by default, as the savings are too
+/// small to be worth it.
+pub const DEFAULT_MIN_SIZE: usize = 1024;
+
+/// The default for [Compress::max_decompressed_size], which is the same as the server's default
+/// limit on the size of (uncompressed) request bodies.
+pub const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = DEFAULT_MAX_BODY_SIZE;
+
+/// The value of the `Accept-Encoding` header sent with `415` responses to requests whose bodies
+/// use an unsupported coding.
+const SUPPORTED: &str = "br, gzip, deflate";
+
+/// A content coding which Puck can compress and decompress.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// Brotli (`br`).
+    Brotli,
+    /// Gzip (`gzip`).
+    Gzip,
+    /// The zlib format (which HTTP calls `deflate`).
+    Deflate,
+}
+
+impl Encoding {
+    /// The encodings, in the order they are preferred in when the client has no preference.
+    const ALL: [Encoding; 3] = [Encoding::Brotli, Encoding::Gzip, Encoding::Deflate];
+
+    /// The name of the encoding, as used in the `Accept-Encoding` and `Content-Encoding`
+    /// headers.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    /// Looks up an encoding by name (case-insensitively).
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name.to_ascii_lowercase().as_str() {
+            "br" => Some(Encoding::Brotli),
+            "gzip" | "x-gzip" => Some(Encoding::Gzip),
+            "deflate" => Some(Encoding::Deflate),
+            _ => None,
+        }
+    }
+
+    /// Compresses `body` as it is read.
+    pub fn encode(&self, body: Body) -> Body {
+        let mime = body.mime.clone();
+        let reader: Box<dyn Read> = match self {
+            Encoding::Brotli => Box::new(brotli::CompressorReader::new(body, 4096, 5, 22)),
+            Encoding::Gzip => Box::new(GzEncoder::new(body, Compression::default())),
+            Encoding::Deflate => Box::new(ZlibEncoder::new(body, Compression::default())),
+        };
+        Body::from_reader(BufReader::new(reader), None).with_mime(mime)
+    }
+
+    /// Decompresses `body` as it is read.
+    pub fn decode(&self, body: Body) -> Body {
+        let mime = body.mime.clone();
+        let reader: Box<dyn Read> = match self {
+            Encoding::Brotli => Box::new(brotli::Decompressor::new(body, 4096)),
+            Encoding::Gzip => Box::new(MultiGzDecoder::new(body)),
+            Encoding::Deflate => Box::new(ZlibDecoder::new(body)),
+        };
+        Body::from_reader(BufReader::new(reader), None).with_mime(mime)
+    }
+}
+
+/// Chooses the encoding to compress a response with, from the value of the request's
+/// `Accept-Encoding` header.
+///
+/// The encoding with the highest quality value is chosen, preferring brotli, then gzip, then
+/// deflate when several are equally acceptable. Returns `None` if the client does not accept any
+/// of them.
+pub fn preferred_encoding<'a>(
+    accept_encoding: impl IntoIterator<Item = &'a str>,
+) -> Option<Encoding> {
+    let mut qualities: [Option<f32>; 3] = [None; 3];
+    let mut wildcard = None;
+
+    for item in accept_encoding {
+        let mut parts = item.split(';').map(str::trim);
+        let coding = parts.next().unwrap_or_default();
+        let quality = parts
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+            .map_or(Some(1.0), |(_, q)| q.trim().parse::<f32>().ok());
+        let quality = match quality {
+            Some(quality) if (0.0..=1.0).contains(&quality) => quality,
+            _ => continue,
+        };
+
+        if coding == "*" {
+            wildcard = Some(quality);
+        } else if let Some(encoding) = Encoding::from_name(coding) {
+            let index = Encoding::ALL.iter().position(|e| *e == encoding).unwrap();
+            qualities[index] = Some(quality);
+        }
+    }
+
+    let mut best: Option<(Encoding, f32)> = None;
+    for (encoding, quality) in Encoding::ALL.iter().zip(qualities.iter()) {
+        let quality = quality.or(wildcard).unwrap_or(0.0);
+        if quality > 0.0 && best.map_or(true, |(_, best)| quality > best) {
+            best = Some((*encoding, quality));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Whether it is worth compressing bodies of the given type (formats which are already
+/// compressed, such as most images, audio and video, are not).
+pub fn is_compressible(mime: &Mime) -> bool {
+    match mime.basetype() {
+        "text" => true,
+        "image" => mime.subtype() == "svg+xml",
+        "audio" | "video" => false,
+        "font" => !matches!(mime.subtype(), "woff" | "woff2"),
+        _ => !matches!(
+            mime.subtype(),
+            "zip"
+                | "gzip"
+                | "x-gzip"
+                | "zstd"
+                | "x-bzip2"
+                | "x-xz"
+                | "x-7z-compressed"
+                | "x-rar-compressed"
+                | "pdf"
+                | "octet-stream"
+        ),
+    }
+}
+
+/// Middleware which compresses responses using the best encoding the client accepts, and
+/// decompresses request bodies sent with a `Content-Encoding`.
+///
+/// Compressed responses are sent with a `Content-Encoding` header, and (as their length is not
+/// known until they have been compressed) using the `chunked` transfer coding. Responses which
+/// are already encoded, are partial (`206`), are of a type which is already compressed, or are
+/// shorter than the minimum size are sent as they are.
+#[derive(Debug, Clone)]
+pub struct Compress {
+    min_size: usize,
+    decompress_requests: bool,
+    max_decompressed_size: usize,
+}
+
+impl Default for Compress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compress {
+    /// Create the middleware, with the default minimum size ([DEFAULT_MIN_SIZE]) and request
+    /// decompression enabled (up to [DEFAULT_MAX_DECOMPRESSED_SIZE]).
+    pub fn new() -> Compress {
+        Compress {
+            min_size: DEFAULT_MIN_SIZE,
+            decompress_requests: true,
+            max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+        }
+    }
+
+    /// Set the length (in bytes) below which bodies are not compressed. Bodies whose length is
+    /// not known in advance are always compressed.
+    pub fn min_size(mut self, min_size: usize) -> Compress {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Set whether request bodies sent with a `Content-Encoding` header are decompressed before
+    /// being passed on (requests using an encoding which is not supported are rejected with
+    /// `415 Unsupported Media Type`). If this is disabled, request bodies are passed on as they
+    /// are.
+    pub fn decompress_requests(mut self, decompress_requests: bool) -> Compress {
+        self.decompress_requests = decompress_requests;
+        self
+    }
+
+    /// Set the largest size (in bytes) which a request body may decompress to. A small
+    /// compressed body can decompress to a very large one, so reading past this limit fails, and
+    /// the request is answered with `413 Content Too Large` (as with
+    /// [ServerConfig::max_body_size](crate::core::ServerConfig::max_body_size), which only limits
+    /// the compressed body). This is [DEFAULT_MAX_DECOMPRESSED_SIZE] by default.
+    pub fn max_decompressed_size(mut self, max_decompressed_size: usize) -> Compress {
+        self.max_decompressed_size = max_decompressed_size;
+        self
+    }
+
+    /// Compress the response (if it is worth compressing) using `encoding`.
+    fn compress(&self, mut response: Response, encoding: Option<Encoding>) -> Response {
+        let status = response.status;
+        if status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::PARTIAL_CONTENT
+            || status == StatusCode::NOT_MODIFIED
+            || response.headers.contains_key("content-encoding")
+            || response.headers.contains_key("content-range")
+            || response
+                .body
+                .length
+                .map_or(false, |length| length < self.min_size)
+        {
+            return response;
+        }
+
+        let mime = response
+            .headers
+            .get("content-type")
+            .and_then(|content_type| content_type.parse().ok())
+            .unwrap_or_else(|| response.body.mime.clone());
+        if !is_compressible(&mime) {
+            return response;
+        }
+
+        // whether this response is compressed depends on the request's `Accept-Encoding`, so
+        // caches need to know to store a copy for each encoding
+        if !response
+            .headers
+            .get_list("vary")
+            .any(|field| field == "*" || field.eq_ignore_ascii_case("accept-encoding"))
+        {
+            response
+                .headers
+                .append_unchecked("Vary".to_string(), "Accept-Encoding".to_string());
+        }
+
+        let encoding = match encoding {
+            Some(encoding) => encoding,
+            None => return response,
+        };
+
+        response.headers.remove("content-length");
+        // ranges of the uncompressed body would not line up with the compressed one
+        response.headers.remove("accept-ranges");
+        // the compressed body is not byte-for-byte the same as the uncompressed one
+        if let Some(etag) = response.headers.get("etag") {
+            if !etag.starts_with("W/") {
+                let etag = format!("W/{}", etag);
+                response.headers.insert_unchecked("ETag".to_string(), etag);
+            }
+        }
+        response.headers.insert_unchecked(
+            "Content-Encoding".to_string(),
+            encoding.as_str().to_string(),
+        );
+
+        let body = std::mem::replace(&mut response.body, Body::empty());
+        response.body = encoding.encode(body);
+        response
+    }
+}
+
+/// Decompresses the body of `req` (cutting it off once it is longer than `max_size`) if it has a
+/// `Content-Encoding` header, returning `false` if one of the codings is not supported.
+fn decompress_request(req: &mut Request, max_size: usize) -> bool {
+    let mut encodings = Vec::new();
+    for coding in req.headers.get_list("content-encoding") {
+        if coding.eq_ignore_ascii_case("identity") {
+            continue;
+        }
+        match Encoding::from_name(coding) {
+            Some(encoding) => encodings.push(encoding),
+            None => return false,
+        }
+    }
+    if encodings.is_empty() {
+        return true;
+    }
+
+    // codings are listed in the order they were applied, so they are removed in reverse
+    let mut body = std::mem::replace(&mut req.body, Body::empty());
+    for encoding in encodings.iter().rev() {
+        body = encoding.decode(body);
+    }
+    let mime = body.mime.clone();
+    req.body =
+        Body::from_reader(BufReader::new(Limited::new(body, max_size)), None).with_mime(mime);
+    req.headers.remove("content-encoding");
+    req.headers.remove("content-length");
+    true
+}
+
+impl<STATE> Middleware<STATE> for Compress {
+    fn handle(
+        &self,
+        mut req: Request,
+        stream: Stream,
+        state: STATE,
+        next: &dyn Handler<STATE>,
+    ) -> UsedStream {
+        if self.decompress_requests && !decompress_request(&mut req, self.max_decompressed_size) {
+            let mut response = Response::error(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            response
+                .headers
+                .insert_unchecked("Accept-Encoding".to_string(), SUPPORTED.to_string());
+            return match stream.respond(response) {
+                Ok(stream) => stream,
+                Err(_) => UsedStream::empty(),
+            };
+        }
+
+        let encoding = preferred_encoding(req.headers.get_list("accept-encoding"));
+        let this = self.clone();
+        let stream = stream.map_response(move |response| this.compress(response, encoding));
+        next.handle(req, stream, state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Read, Write};
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use crate::{
+        body::{
+            mime::{JSON, PNG},
+            read_error_status, Body,
+        },
+        request::Method,
+        response::status::StatusCode,
+        Request, Response,
+    };
+
+    use super::{decompress_request, preferred_encoding, Compress, Encoding};
+
+    #[lunatic::test]
+    fn test_preferred_encoding() {
+        let preferred = |value: &str| preferred_encoding(value.split(',').map(str::trim));
+
+        assert_eq!(preferred("gzip, deflate, br"), Some(Encoding::Brotli));
+        assert_eq!(preferred("gzip;q=1.0, br;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(preferred("deflate, *;q=0.1"), Some(Encoding::Deflate));
+        assert_eq!(preferred("*"), Some(Encoding::Brotli));
+        assert_eq!(preferred("br;q=0, *"), Some(Encoding::Gzip));
+        assert_eq!(preferred("identity"), None);
+        assert_eq!(preferred("gzip;q=0"), None);
+        assert_eq!(preferred("gzip;q=2"), None);
+        assert_eq!(preferred(""), None);
+    }
+
+    fn text(length: usize) -> Response {
+        Response::build()
+            .header("Content-Type", JSON)
+            .body(Body::from_string("a".repeat(length)).with_mime(JSON))
+            .build()
+    }
+
+    #[lunatic::test]
+    fn test_compress() {
+        let compress = Compress::new();
+
+        for encoding in Encoding::ALL.iter() {
+            let mut response = compress.compress(text(2000), Some(*encoding));
+            assert_eq!(
+                response.headers().get("Content-Encoding"),
+                Some(encoding.as_str())
+            );
+            assert_eq!(response.headers().get("Vary"), Some("Accept-Encoding"));
+            assert_eq!(response.headers().get("Content-Length"), None);
+            assert_eq!(response.body.length, None);
+
+            let compressed = response.take_body();
+            let decompressed = encoding.decode(compressed).into_string().unwrap();
+            assert_eq!(decompressed, "a".repeat(2000));
+        }
+
+        // the response still varies by `Accept-Encoding` when it is not compressed
+        let response = compress.compress(text(2000), None);
+        assert_eq!(response.headers().get("Content-Encoding"), None);
+        assert_eq!(response.headers().get("Vary"), Some("Accept-Encoding"));
+
+        let response = compress.compress(text(10), Some(Encoding::Gzip));
+        assert_eq!(response.headers().get("Content-Encoding"), None);
+        assert_eq!(response.headers().get("Vary"), None);
+
+        let png = Response::build()
+            .header("Content-Type", PNG)
+            .body(Body::from_reader(Cursor::new(vec![0; 2000]), Some(2000)))
+            .build();
+        let response = compress.compress(png, Some(Encoding::Gzip));
+        assert_eq!(response.headers().get("Content-Encoding"), None);
+    }
+
+    #[lunatic::test]
+    fn test_decompress_request() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"hello world").unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut req = Request::build("http://example.com/")
+            .method(Method::Post)
+            .header("Content-Encoding", "gzip")
+            .header("Content-Length", compressed.len().to_string())
+            .body(Body::from_reader(Cursor::new(compressed), None))
+            .build();
+        assert!(decompress_request(&mut req, 100));
+        assert_eq!(req.headers().get("Content-Encoding"), None);
+        let mut body = String::new();
+        req.take_body().read_to_string(&mut body).unwrap();
+        assert_eq!(body, "hello world");
+
+        let mut req = Request::build("http://example.com/")
+            .method(Method::Post)
+            .header("Content-Encoding", "zstd")
+            .build();
+        assert!(!decompress_request(&mut req, 100));
+    }
+
+    #[lunatic::test]
+    fn test_decompressed_size_limit() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&[0; 10_000]).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut req = Request::build("http://example.com/")
+            .method(Method::Post)
+            .header("Content-Encoding", "gzip")
+            .body(Body::from_reader(Cursor::new(compressed), None))
+            .build();
+        assert!(decompress_request(&mut req, 9_999));
+        let error = req.take_body().read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(read_error_status(&error), StatusCode::CONTENT_TOO_LARGE);
+    }
+}
@@ -0,0 +1,555 @@
+//! Cookies: reading them from requests, setting them in responses, and signing or encrypting
+//! their values so that clients cannot tamper with (or read) them.
+//!
+//! See [RFC 6265](https://www.rfc-editor.org/rfc/rfc6265).
+
+use std::{
+    fmt,
+    time::{Duration, SystemTime},
+};
+
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Nonce,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::request::is_token_char;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The length of a base64-encoded signature (of a signed cookie).
+const SIGNATURE_LENGTH: usize = 43;
+
+/// The length of the nonce which an encrypted cookie's value starts with.
+const NONCE_LENGTH: usize = 12;
+
+/// A cookie to send to the client in a `Set-Cookie` header (see
+/// [ResponseBuilder::cookie](crate::response::builder::ResponseBuilder::cookie)).
+///
+/// ```ignore
+/// let cookie = Cookie::new("theme", "dark")
+///     .path("/")
+///     .max_age(Duration::from_secs(60 * 60 * 24))
+///     .same_site(SameSite::Lax);
+/// assert_eq!(
+///     cookie.to_string(),
+///     "theme=dark; Max-Age=86400; Path=/; SameSite=Lax"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    max_age: Option<Duration>,
+    expires: Option<SystemTime>,
+    path: Option<String>,
+    domain: Option<String>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+/// The value of a cookie's `SameSite` attribute, which controls whether it is sent with requests
+/// from other sites.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    /// The cookie is only sent with requests from the same site.
+    Strict,
+    /// The cookie is also sent when the user navigates to the site from another site (this is
+    /// what most browsers do if the attribute is not set).
+    Lax,
+    /// The cookie is sent with all requests. Browsers only accept this for cookies which are
+    /// also [Cookie::secure].
+    None,
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        })
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+/// An error encountered when constructing a cookie.
+pub enum InvalidCookie {
+    #[error("`{0}` is not a valid cookie name")]
+    /// The name was empty or contained characters which are not allowed.
+    InvalidName(String),
+    #[error("the value of the cookie `{0}` contains characters which are not allowed")]
+    /// The value contained characters which are not allowed (such as spaces, commas,
+    /// semicolons or quotes).
+    InvalidValue(String),
+}
+
+fn is_cookie_octet(byte: u8) -> bool {
+    matches!(byte, 0x21 | 0x23..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B | 0x5D..=0x7E)
+}
+
+impl Cookie {
+    /// Create a cookie with the given name and value. This method panics if the name is not a
+    /// valid token, or the value contains characters which are not allowed in cookies (see
+    /// [Cookie::try_new]).
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Cookie {
+        Cookie::try_new(name, value).expect("`Cookie::new` was given an invalid cookie")
+    }
+
+    /// Create a cookie with the given name and value, returning an error if the name is not a
+    /// valid token or the value contains characters which are not allowed in cookies (control
+    /// characters, whitespace, double quotes, commas, semicolons, backslashes and non-ASCII
+    /// characters). Values which might contain these should be encoded (e.g. percent-encoded)
+    /// first.
+    pub fn try_new(
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<Cookie, InvalidCookie> {
+        let name = name.into();
+        let value = value.into();
+        if name.is_empty() || !name.bytes().all(is_token_char) {
+            return Err(InvalidCookie::InvalidName(name));
+        }
+        if !value.bytes().all(is_cookie_octet) {
+            return Err(InvalidCookie::InvalidValue(name));
+        }
+        Ok(Cookie {
+            name,
+            value,
+            max_age: None,
+            expires: None,
+            path: None,
+            domain: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        })
+    }
+
+    /// A cookie which tells the client to delete the cookie with the given name (by setting it
+    /// to an empty value which has already expired).
+    ///
+    /// The cookie is only deleted if the path and domain match those it was set with.
+    pub fn removal(name: impl Into<String>) -> Cookie {
+        Cookie::new(name, "")
+            .max_age(Duration::from_secs(0))
+            .expires(SystemTime::UNIX_EPOCH)
+    }
+
+    /// The name of the cookie.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The value of the cookie.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Set how long the cookie lasts for (the `Max-Age` attribute). Clients which understand
+    /// both prefer this to [Cookie::expires].
+    pub fn max_age(mut self, max_age: Duration) -> Cookie {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Set when the cookie expires (the `Expires` attribute). If neither this nor
+    /// [Cookie::max_age] is set, the cookie is deleted when the browser is closed.
+    pub fn expires(mut self, expires: SystemTime) -> Cookie {
+        self.expires = Some(expires);
+        self
+    }
+
+    /// Set the path (and the paths below it) which the cookie is sent to. This method panics if
+    /// the path contains a semicolon or a control character.
+    pub fn path(mut self, path: impl Into<String>) -> Cookie {
+        self.path = Some(attribute(path.into()));
+        self
+    }
+
+    /// Set the domain (and its subdomains) which the cookie is sent to. If this is not set, the
+    /// cookie is only sent to the host which set it. This method panics if the domain contains
+    /// a semicolon or a control character.
+    pub fn domain(mut self, domain: impl Into<String>) -> Cookie {
+        self.domain = Some(attribute(domain.into()));
+        self
+    }
+
+    /// Set whether the cookie should only be sent over HTTPS.
+    pub fn secure(mut self, secure: bool) -> Cookie {
+        self.secure = secure;
+        self
+    }
+
+    /// Set whether the cookie should be hidden from JavaScript running in the browser.
+    pub fn http_only(mut self, http_only: bool) -> Cookie {
+        self.http_only = http_only;
+        self
+    }
+
+    /// Set the `SameSite` attribute of the cookie.
+    pub fn same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// Sign the cookie's value with `key`, so that it can be read by the client but any changes
+    /// to it will be detected (see [Cookies::signed]).
+    pub fn signed(mut self, key: &Key) -> Cookie {
+        let mut mac = key.mac(&self.name);
+        mac.update(self.value.as_bytes());
+        let signature = base64::encode_config(mac.finalize().into_bytes(), base64::URL_SAFE_NO_PAD);
+        self.value = signature + &self.value;
+        self
+    }
+
+    /// Encrypt the cookie's value with `key`, so that the client can neither read nor change it
+    /// (see [Cookies::private]).
+    pub fn encrypted(mut self, key: &Key) -> Cookie {
+        let mut nonce = [0; NONCE_LENGTH];
+        random_bytes(&mut nonce);
+        let ciphertext = key
+            .cipher()
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: self.value.as_bytes(),
+                    aad: self.name.as_bytes(),
+                },
+            )
+            .expect("encrypting a cookie failed");
+
+        let mut value = nonce.to_vec();
+        value.extend(ciphertext);
+        self.value = base64::encode_config(value, base64::URL_SAFE_NO_PAD);
+        self
+    }
+}
+
+/// Fills `bytes` with random bytes from the operating system, for anything which must not be
+/// guessable (keys, nonces and session IDs).
+pub(crate) fn random_bytes(bytes: &mut [u8]) {
+    getrandom::getrandom(bytes).expect("failed to get random bytes from the operating system");
+}
+
+fn attribute(value: String) -> String {
+    assert!(
+        !value
+            .bytes()
+            .any(|byte| byte == b';' || byte.is_ascii_control()),
+        "`Cookie` was given an attribute containing a semicolon or control character"
+    );
+    value
+}
+
+impl fmt::Display for Cookie {
+    /// Formats the cookie as the value of a `Set-Cookie` header.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", httpdate::fmt_http_date(expires))?;
+        }
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site)?;
+        }
+        Ok(())
+    }
+}
+
+/// The cookies a client sent with a request (see [Request::cookies](crate::Request::cookies)).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cookies {
+    pairs: Vec<(String, String)>,
+}
+
+impl Cookies {
+    /// Parse the value of a `Cookie` header. Malformed cookies are skipped.
+    pub fn parse(header: &str) -> Cookies {
+        let mut cookies = Cookies::default();
+        cookies.extend(header);
+        cookies
+    }
+
+    pub(crate) fn extend(&mut self, header: &str) {
+        for pair in header.split(';') {
+            let (name, value) = match pair.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => continue,
+            };
+            if name.is_empty() {
+                continue;
+            }
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+            self.pairs.push((name.to_string(), value.to_string()));
+        }
+    }
+
+    /// The value of the cookie with the given name (if the client sent more than one, this is
+    /// the first).
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Iterate over the names and values of the cookies, in the order they were sent.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// The number of cookies.
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    /// Whether there are no cookies.
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    /// Read cookies which were set with [Cookie::signed], verifying that they have not been
+    /// changed.
+    pub fn signed<'a>(&'a self, key: &'a Key) -> SignedCookies<'a> {
+        SignedCookies { cookies: self, key }
+    }
+
+    /// Read cookies which were set with [Cookie::encrypted], decrypting their values.
+    pub fn private<'a>(&'a self, key: &'a Key) -> PrivateCookies<'a> {
+        PrivateCookies { cookies: self, key }
+    }
+}
+
+/// The signed cookies sent with a request (see [Cookies::signed]).
+#[derive(Debug, Clone, Copy)]
+pub struct SignedCookies<'a> {
+    cookies: &'a Cookies,
+    key: &'a Key,
+}
+
+impl<'a> SignedCookies<'a> {
+    /// The value of the cookie with the given name, or `None` if there is no such cookie or its
+    /// signature is not valid.
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        let value = self.cookies.get(name)?;
+        if value.len() < SIGNATURE_LENGTH || !value.is_char_boundary(SIGNATURE_LENGTH) {
+            return None;
+        }
+        let (signature, value) = value.split_at(SIGNATURE_LENGTH);
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).ok()?;
+
+        let mut mac = self.key.mac(name);
+        mac.update(value.as_bytes());
+        mac.verify_slice(&signature).ok()?;
+        Some(value)
+    }
+}
+
+/// The encrypted cookies sent with a request (see [Cookies::private]).
+#[derive(Debug, Clone, Copy)]
+pub struct PrivateCookies<'a> {
+    cookies: &'a Cookies,
+    key: &'a Key,
+}
+
+impl<'a> PrivateCookies<'a> {
+    /// The decrypted value of the cookie with the given name, or `None` if there is no such
+    /// cookie or it could not be decrypted (e.g. because it has been changed).
+    pub fn get(&self, name: &str) -> Option<String> {
+        let value = self.cookies.get(name)?;
+        let value = base64::decode_config(value, base64::URL_SAFE_NO_PAD).ok()?;
+        if value.len() < NONCE_LENGTH {
+            return None;
+        }
+        let (nonce, ciphertext) = value.split_at(NONCE_LENGTH);
+
+        let plaintext = self
+            .key
+            .cipher()
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: name.as_bytes(),
+                },
+            )
+            .ok()?;
+        String::from_utf8(plaintext).ok()
+    }
+}
+
+/// The secret key used to sign and encrypt cookies.
+///
+/// The same key must be used for every request (and by every server, if there are several), so
+/// it should usually be loaded from configuration with [Key::from_secret]. Anyone who knows the
+/// key can forge cookies.
+#[derive(Clone)]
+pub struct Key {
+    signing: [u8; 32],
+    encryption: [u8; 32],
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Key").finish_non_exhaustive()
+    }
+}
+
+impl Key {
+    /// Derive a key from a secret. This method panics if the secret is shorter than 32 bytes.
+    pub fn from_secret(secret: &[u8]) -> Key {
+        assert!(
+            secret.len() >= 32,
+            "cookie secrets must be at least 32 bytes long"
+        );
+
+        let derive = |purpose: &[u8]| {
+            let mut mac =
+                <HmacSha256 as Mac>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+            mac.update(purpose);
+            let mut key = [0; 32];
+            key.copy_from_slice(&mac.finalize().into_bytes());
+            key
+        };
+        Key {
+            signing: derive(b"puck cookie signing"),
+            encryption: derive(b"puck cookie encryption"),
+        }
+    }
+
+    /// Generate a random key. Cookies signed or encrypted with it can no longer be read once the
+    /// server restarts.
+    pub fn generate() -> Key {
+        let mut secret = [0; 64];
+        random_bytes(&mut secret);
+        Key::from_secret(&secret)
+    }
+
+    /// A MAC, which the value of the cookie called `name` should be added to.
+    fn mac(&self, name: &str) -> HmacSha256 {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.signing)
+            .expect("HMAC accepts keys of any size");
+        mac.update(name.as_bytes());
+        mac.update(b"=");
+        mac
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new_from_slice(&self.encryption).expect("the key is the right size")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime};
+
+    use crate::{request::Method, Request, Response};
+
+    use super::{Cookie, Cookies, InvalidCookie, Key, SameSite};
+
+    #[lunatic::test]
+    fn test_set_cookie() {
+        let cookie = Cookie::new("session", "abc123")
+            .max_age(Duration::from_secs(3600))
+            .expires(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000))
+            .path("/")
+            .domain("example.com")
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::Strict);
+        assert_eq!(
+            cookie.to_string(),
+            "session=abc123; Max-Age=3600; Expires=Sun, 09 Sep 2001 01:46:40 GMT; Path=/; \
+            Domain=example.com; Secure; HttpOnly; SameSite=Strict"
+        );
+
+        let response = Response::build()
+            .cookie(Cookie::new("a", "1"))
+            .cookie(Cookie::removal("b"))
+            .build();
+        assert_eq!(
+            response.headers().get_all("Set-Cookie").collect::<Vec<_>>(),
+            vec![
+                "a=1",
+                "b=; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT"
+            ]
+        );
+
+        assert_eq!(
+            Cookie::try_new("a b", "c"),
+            Err(InvalidCookie::InvalidName("a b".to_string()))
+        );
+        assert_eq!(
+            Cookie::try_new("a", "c;d"),
+            Err(InvalidCookie::InvalidValue("a".to_string()))
+        );
+    }
+
+    #[lunatic::test]
+    fn test_parse() {
+        let req = Request::build("http://example.com/")
+            .method(Method::Get)
+            .header("Cookie", "a=1; b=\"two\";c; =3;  d = 4 ")
+            .build();
+        let cookies = req.cookies();
+        assert_eq!(
+            cookies.iter().collect::<Vec<_>>(),
+            vec![("a", "1"), ("b", "two"), ("d", "4")]
+        );
+        assert_eq!(cookies.get("d"), Some("4"));
+        assert_eq!(cookies.get("c"), None);
+    }
+
+    #[lunatic::test]
+    fn test_signed() {
+        let key = Key::from_secret(&[7; 32]);
+        let cookie = Cookie::new("user", "42").signed(&key);
+        assert!(cookie.value().ends_with("42"));
+
+        let cookies = Cookies::parse(&format!("user={}", cookie.value()));
+        assert_eq!(cookies.signed(&key).get("user"), Some("42"));
+        // a different key, name or value
+        assert_eq!(cookies.signed(&Key::generate()).get("user"), None);
+        let cookies = Cookies::parse(&format!("admin={}", cookie.value()));
+        assert_eq!(cookies.signed(&key).get("admin"), None);
+        let tampered = cookie.value().replace("42", "43");
+        let cookies = Cookies::parse(&format!("user={}", tampered));
+        assert_eq!(cookies.signed(&key).get("user"), None);
+    }
+
+    #[lunatic::test]
+    fn test_encrypted() {
+        let key = Key::from_secret(&[7; 32]);
+        let cookie = Cookie::new("user", "42").encrypted(&key);
+        assert!(!cookie.value().contains("42"));
+
+        let cookies = Cookies::parse(&format!("user={}", cookie.value()));
+        assert_eq!(cookies.private(&key).get("user"), Some("42".to_string()));
+        assert_eq!(cookies.private(&Key::generate()).get("user"), None);
+        let cookies = Cookies::parse(&format!("admin={}", cookie.value()));
+        assert_eq!(cookies.private(&key).get("admin"), None);
+    }
+}
@@ -0,0 +1,250 @@
+//! Limits on the requests (and connections) which the server accepts, and how long it waits for
+//! them.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// The default for [ServerConfig::max_headers].
+pub const DEFAULT_MAX_HEADERS: usize = 100;
+
+/// The default for [ServerConfig::max_header_bytes].
+pub const DEFAULT_MAX_HEADER_BYTES: usize = 16 * 1024;
+
+/// The default for [ServerConfig::max_request_line].
+pub const DEFAULT_MAX_REQUEST_LINE: usize = 8 * 1024;
+
+/// The default for [ServerConfig::max_body_size].
+pub const DEFAULT_MAX_BODY_SIZE: usize = 50 * 1024 * 1024;
+
+/// The default for [ServerConfig::head_timeout].
+pub const DEFAULT_HEAD_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// The default for [ServerConfig::body_timeout].
+pub const DEFAULT_BODY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The default for [ServerConfig::idle_timeout].
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The default for [ServerConfig::write_timeout].
+pub const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The default for [ServerConfig::min_body_rate], in bytes per second.
+pub const DEFAULT_MIN_BODY_RATE: u64 = 1024;
+
+/// The default for [ServerConfig::shutdown_timeout].
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The default for [ServerConfig::retry_after].
+pub const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// How long a client may spend sending a body before [ServerConfig::min_body_rate] is enforced.
+pub const MIN_BODY_RATE_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// How the server treats the requests it receives (see [Core::bind](super::Core::bind)).
+///
+/// Requests which exceed these limits are rejected while they are being parsed, before they reach
+/// a handler:
+/// - a request line longer than [ServerConfig::max_request_line] receives a `414 URI Too Long`
+///   response
+/// - more than [ServerConfig::max_headers] headers, or headers longer in total than
+///   [ServerConfig::max_header_bytes], receive a `431 Request Header Fields Too Large` response
+/// - a body with a `Content-Length` greater than [ServerConfig::max_body_size] receives a
+///   `413 Content Too Large` response
+///
+/// The connection is closed after any of these responses have been sent.
+///
+/// The server also only waits so long for a client (see [ServerConfig::head_timeout] and the
+/// other timeouts), so that clients which send their requests very slowly (or not at all) cannot
+/// tie up the processes which serve them.
+///
+/// The number of connections the server keeps open at once can be limited too (see
+/// [ServerConfig::max_connections]).
+///
+/// ```ignore
+/// Core::bind("localhost:8080", state, ServerConfig::default().max_headers(50))
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerConfig {
+    pub(crate) max_headers: usize,
+    pub(crate) max_header_bytes: usize,
+    pub(crate) max_request_line: usize,
+    pub(crate) max_body_size: usize,
+    pub(crate) head_timeout: Duration,
+    pub(crate) body_timeout: Duration,
+    pub(crate) idle_timeout: Duration,
+    pub(crate) write_timeout: Duration,
+    pub(crate) min_body_rate: Option<u64>,
+    pub(crate) shutdown_timeout: Duration,
+    pub(crate) max_connections: Option<usize>,
+    pub(crate) max_connections_per_ip: Option<usize>,
+    pub(crate) when_full: WhenFull,
+    pub(crate) retry_after: Duration,
+}
+
+/// What the server does with a new connection when it already has
+/// [ServerConfig::max_connections] open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WhenFull {
+    /// Stop accepting connections until one of the open connections is closed. In the meantime,
+    /// new connections wait in the operating system's queue (and are refused once that is full).
+    Wait,
+    /// Send the new connection a `503 Service Unavailable` response (with a `Retry-After` header,
+    /// see [ServerConfig::retry_after]) and close it.
+    Reject,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            max_headers: DEFAULT_MAX_HEADERS,
+            max_header_bytes: DEFAULT_MAX_HEADER_BYTES,
+            max_request_line: DEFAULT_MAX_REQUEST_LINE,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            head_timeout: DEFAULT_HEAD_TIMEOUT,
+            body_timeout: DEFAULT_BODY_TIMEOUT,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            write_timeout: DEFAULT_WRITE_TIMEOUT,
+            min_body_rate: Some(DEFAULT_MIN_BODY_RATE),
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            max_connections: None,
+            max_connections_per_ip: None,
+            when_full: WhenFull::Wait,
+            retry_after: DEFAULT_RETRY_AFTER,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Set the largest number of headers a request may have. This is [DEFAULT_MAX_HEADERS] by
+    /// default.
+    pub fn max_headers(mut self, max_headers: usize) -> Self {
+        self.max_headers = max_headers;
+        self
+    }
+
+    /// Set the largest number of bytes the headers of a request may take up in total (including
+    /// the line break after each header, but not the request line). This is
+    /// [DEFAULT_MAX_HEADER_BYTES] by default.
+    pub fn max_header_bytes(mut self, max_header_bytes: usize) -> Self {
+        self.max_header_bytes = max_header_bytes;
+        self
+    }
+
+    /// Set the longest request line (e.g. `GET /index.html HTTP/1.1`, including the line break)
+    /// which a request may have, which limits the length of the URL. This is
+    /// [DEFAULT_MAX_REQUEST_LINE] by default.
+    pub fn max_request_line(mut self, max_request_line: usize) -> Self {
+        self.max_request_line = max_request_line;
+        self
+    }
+
+    /// Set the largest body (in bytes) which a request may have. This is [DEFAULT_MAX_BODY_SIZE]
+    /// by default.
+    ///
+    /// Bodies sent using the chunked transfer coding are cut off once they exceed this, in which
+    /// case reading the body fails (and any body error's `status`, e.g.
+    /// [JsonError::status](crate::body::json::JsonError::status), is
+    /// `413 Content Too Large`).
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// Set how long a client has to send the request line and headers of a request, from when
+    /// the first byte of the request arrives (or, for the first request on a connection, from
+    /// when the connection is opened). This is [DEFAULT_HEAD_TIMEOUT] by default.
+    ///
+    /// If part of a request has arrived when this runs out, the client is sent a
+    /// `408 Request Timeout` response. Either way, the connection is closed.
+    pub fn head_timeout(mut self, head_timeout: Duration) -> Self {
+        self.head_timeout = head_timeout;
+        self
+    }
+
+    /// Set how long the server waits for more of a request's body to arrive. This is
+    /// [DEFAULT_BODY_TIMEOUT] by default.
+    ///
+    /// If this runs out, reading the body fails (and any body error's `status` is
+    /// `408 Request Timeout`), and the connection is closed once the handler has responded.
+    pub fn body_timeout(mut self, body_timeout: Duration) -> Self {
+        self.body_timeout = body_timeout;
+        self
+    }
+
+    /// Set how long a connection is kept open while waiting for the client to start sending
+    /// another request. This is [DEFAULT_IDLE_TIMEOUT] by default.
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Set how long the server waits for the client to accept more of a response before giving
+    /// up and closing the connection. This is [DEFAULT_WRITE_TIMEOUT] by default.
+    pub fn write_timeout(mut self, write_timeout: Duration) -> Self {
+        self.write_timeout = write_timeout;
+        self
+    }
+
+    /// Set the slowest rate (in bytes per second) at which a client may send a body, or `None`
+    /// to allow any rate. This is [DEFAULT_MIN_BODY_RATE] by default.
+    ///
+    /// This is what stops a client from keeping a connection open by sending a body a byte at a
+    /// time, each just before [ServerConfig::body_timeout] runs out. Only the time spent waiting
+    /// for the client counts (not the time the handler spends processing the body), and it is
+    /// only enforced once the server has spent [MIN_BODY_RATE_GRACE_PERIOD] waiting for the
+    /// body. If the client is too slow, reading the body fails as if
+    /// [ServerConfig::body_timeout] had run out.
+    pub fn min_body_rate(mut self, min_body_rate: Option<u64>) -> Self {
+        self.min_body_rate = min_body_rate;
+        self
+    }
+
+    /// Set how long the server gives the requests which are being handled (and any open
+    /// WebSocket connections) to finish once it has been asked to shut down (see
+    /// [ShutdownHandle](super::ShutdownHandle)). This is [DEFAULT_SHUTDOWN_TIMEOUT] by default.
+    ///
+    /// Connections which are still open once this runs out are closed, and clients which were
+    /// still waiting for a response are sent a `503 Service Unavailable` response.
+    pub fn shutdown_timeout(mut self, shutdown_timeout: Duration) -> Self {
+        self.shutdown_timeout = shutdown_timeout;
+        self
+    }
+
+    /// Set the largest number of connections which the server keeps open at once, or `None` for
+    /// no limit. There is no limit by default.
+    ///
+    /// Each connection is served by its own processes, so this is also a limit on how many of
+    /// them the server starts. What happens to connections beyond the limit is set with
+    /// [ServerConfig::when_full].
+    pub fn max_connections(mut self, max_connections: Option<usize>) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// Set the largest number of connections which the server keeps open at once to a single IP
+    /// address, or `None` for no limit. There is no limit by default.
+    ///
+    /// Connections beyond this limit are always rejected (as with [WhenFull::Reject]), because
+    /// waiting for one client's connections to close would hold up every other client.
+    pub fn max_connections_per_ip(mut self, max_connections_per_ip: Option<usize>) -> Self {
+        self.max_connections_per_ip = max_connections_per_ip;
+        self
+    }
+
+    /// Set what the server does with new connections once it has [ServerConfig::max_connections]
+    /// open. This is [WhenFull::Wait] by default.
+    pub fn when_full(mut self, when_full: WhenFull) -> Self {
+        self.when_full = when_full;
+        self
+    }
+
+    /// Set how long clients whose connections are rejected (see [ServerConfig::when_full] and
+    /// [ServerConfig::max_connections_per_ip]) are told to wait before trying again, in the
+    /// `Retry-After` header. This is rounded up to whole seconds, and is [DEFAULT_RETRY_AFTER] by
+    /// default.
+    pub fn retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+}
@@ -0,0 +1,328 @@
+//! Serves the requests sent over a single TCP connection.
+
+use std::{
+    cell::RefCell,
+    io::{self, BufRead, BufReader, Read},
+    rc::Rc,
+    time::Instant,
+};
+
+use lunatic::net::TcpStream;
+
+use crate::{
+    body::{chunked::ChunkedDecoder, Body, Limited},
+    request::{BodyLength, Method, RequestParseError},
+    Request, Response,
+};
+
+use super::{
+    supervisor::{Event, Supervisor},
+    timeout::{ReadTimeout, TimedReader},
+    ServerConfig, Stream, UsedStream,
+};
+
+/// The most the server reads of the body a handler left unread, before it gives up on reusing the
+/// connection and closes it instead.
+const MAX_DRAIN: u64 = 64 * 1024;
+
+/// The read half of a connection.
+///
+/// This is shared between the connection loop and the `Body` of the request currently being
+/// handled, so that whatever the handler leaves unread (including any pipelined requests which
+/// arrived in the same packet) is still available to the connection once the handler returns.
+#[derive(Clone)]
+struct Connection(Rc<RefCell<BufReader<TimedReader>>>);
+
+impl Connection {
+    fn set_timeout(&self, timeout: ReadTimeout) {
+        self.0.borrow_mut().get_mut().set_timeout(timeout);
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.borrow_mut().read(buf)
+    }
+}
+
+/// The body of the request currently being handled, shared between the handler (inside a
+/// `Body`) and the connection loop (which drains whatever the handler did not read).
+#[derive(Clone)]
+struct SharedBody(Rc<RefCell<Box<dyn Read>>>);
+
+impl Read for SharedBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.borrow_mut().read(buf)
+    }
+}
+
+/// Parses requests from the stream and passes them to `handle`, for as long as both the client
+/// and the handler are happy for the connection to stay open. Requests which exceed the limits in
+/// `config` are rejected.
+///
+/// If there is a `supervisor`, it is kept informed of what is happening on the connection (and of
+/// any panic in this process).
+pub(crate) fn serve(
+    stream: TcpStream,
+    config: &ServerConfig,
+    supervisor: Option<Supervisor>,
+    handle: impl FnMut(Request, Stream) -> UsedStream,
+) {
+    if let Some(supervisor) = &supervisor {
+        supervisor.report_panics();
+    }
+    serve_requests(stream, config, &supervisor, handle);
+
+    if let Some(supervisor) = supervisor {
+        supervisor.notify(Event::Done);
+    }
+}
+
+fn serve_requests(
+    stream: TcpStream,
+    config: &ServerConfig,
+    supervisor: &Option<Supervisor>,
+    mut handle: impl FnMut(Request, Stream) -> UsedStream,
+) {
+    let connection = Connection(Rc::new(RefCell::new(BufReader::with_capacity(
+        10000,
+        TimedReader::new(
+            stream.clone(),
+            ReadTimeout::Deadline(Instant::now() + config.head_timeout),
+        ),
+    ))));
+    let mut first = true;
+
+    loop {
+        // wait for the client to start sending the next request (the first request has to
+        // arrive within the time allowed for its head)
+        if !first {
+            connection.set_timeout(ReadTimeout::Deadline(Instant::now() + config.idle_timeout));
+        }
+        match connection.0.borrow_mut().fill_buf() {
+            // the client closed the connection, or took too long to send anything
+            Ok([]) | Err(_) => return,
+            Ok(_) => {}
+        }
+        if !first {
+            connection.set_timeout(ReadTimeout::Deadline(Instant::now() + config.head_timeout));
+        }
+        first = false;
+
+        let parsed = Request::parse_head(&mut *connection.0.borrow_mut(), config).and_then(|req| {
+            req.map(|req| match req.body_length()? {
+                BodyLength::Known(length) if length > config.max_body_size => {
+                    Err(RequestParseError::BodyTooLarge)
+                }
+                length => Ok((length, req)),
+            })
+            .transpose()
+        });
+
+        let (length, mut req) = match parsed {
+            Ok(Some(parsed)) => parsed,
+            // the client closed the connection
+            Ok(None) => return,
+            // the connection failed, so there is no point in responding
+            Err(RequestParseError::IoError(_)) => return,
+            Err(error) => {
+                // can't do much if this fails
+                let mut error_stream = Stream::new(stream, false, 1);
+                error_stream.write_timeout = Some(config.write_timeout);
+                let _ = error_stream.respond(Response::error(error.status()));
+                return;
+            }
+        };
+
+        // a request with both `Transfer-Encoding` and `Content-Length` may have been framed
+        // differently by whatever passed it on, so the connection cannot safely be reused (see
+        // RFC 9112 section 6.3)
+        let framed_twice =
+            length == BodyLength::Chunked && req.headers().contains_key("content-length");
+        let keep_alive = req.wants_keep_alive() && !framed_twice;
+
+        let (reader, length): (Box<dyn Read>, _) = match length {
+            BodyLength::Chunked => (
+                Box::new(Limited::new(
+                    ChunkedDecoder::new(connection.clone()),
+                    config.max_body_size,
+                )),
+                None,
+            ),
+            BodyLength::Known(length) => (
+                Box::new(connection.clone().take(length as u64)),
+                Some(length),
+            ),
+            // a request without a `Content-Length` or `Transfer-Encoding` has no body
+            BodyLength::Unspecified => (Box::new(io::empty()), Some(0)),
+        };
+        let body = SharedBody(Rc::new(RefCell::new(reader)));
+        req.set_received_body(Body::from_reader(BufReader::new(body.clone()), length));
+        connection.set_timeout(ReadTimeout::Body {
+            timeout: config.body_timeout,
+            min_rate: config.min_body_rate,
+        });
+
+        let mut response_stream = Stream::new(stream.clone(), keep_alive, req.version);
+        response_stream.head = req.method() == &Method::Head;
+        response_stream.supervisor.clone_from(supervisor);
+        response_stream.write_timeout = Some(config.write_timeout);
+
+        if let Some(supervisor) = supervisor {
+            supervisor.notify(Event::Handling(req.request_line()));
+        }
+        let used = (handle)(req, response_stream);
+        if let Some(supervisor) = supervisor {
+            supervisor.notify(Event::Idle);
+        }
+
+        if used.stream.is_none() || !used.keep_alive {
+            return;
+        }
+
+        // skip over anything the handler did not read, so that the next request is parsed from
+        // the right place (unless there is so much left that closing the connection is cheaper)
+        match io::copy(&mut body.clone().take(MAX_DRAIN + 1), &mut io::sink()) {
+            Ok(drained) if drained <= MAX_DRAIN => {}
+            _ => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Read, Write},
+        time::Duration,
+    };
+
+    use lunatic::{
+        net::{TcpListener, TcpStream},
+        Mailbox, Process,
+    };
+
+    use crate::{core::ServerConfig, Response};
+
+    /// Sends the raw request over a real connection to a handler which reads a JSON array from
+    /// the body, and returns the raw response.
+    fn send(config: &ServerConfig, request: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(request.as_bytes()).unwrap();
+
+        let (server, _) = listener.accept().unwrap();
+        super::serve(server, config, None, |mut req, stream| {
+            let response = match req.take_body().json::<Vec<u8>>() {
+                Ok(items) => Response::build().body(items.len().to_string()).build(),
+                Err(error) => Response::error(error.status()),
+            };
+            stream.respond(response).unwrap()
+        });
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[lunatic::test]
+    fn test_body_limit() {
+        let config = ServerConfig::default().max_body_size(8);
+
+        let response = send(
+            &config,
+            "POST / HTTP/1.1\r\nHost: a\r\nContent-Type: application/json\r\n\
+            Content-Length: 5\r\n\r\n[1,2]\
+            POST / HTTP/1.1\r\nHost: a\r\nContent-Type: application/json\r\n\
+            Transfer-Encoding: chunked\r\n\r\n9\r\n[1,2,3,4]\r\n0\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\n2HTTP/1.1 413"));
+
+        let response = send(
+            &config,
+            "POST / HTTP/1.1\r\nHost: a\r\nContent-Type: application/json\r\n\
+            Content-Length: 9\r\n\r\n[1,2,3,4]",
+        );
+        assert!(response.starts_with("HTTP/1.1 413 Content Too Large\r\n"));
+        assert!(response.contains("\r\nConnection: close\r\n"));
+    }
+
+    #[lunatic::test]
+    fn test_unread_body() {
+        let config = ServerConfig::default();
+        // returns how many of the requests were handled
+        let serve = |body_length: usize| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let (server, _) = listener.accept().unwrap();
+            let request = format!(
+                "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: {}\r\n\r\n{}\
+                GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+                body_length,
+                "a".repeat(body_length)
+            );
+            // the request has to be sent while the server is reading it, as it may be too big to
+            // fit in the connection's buffers
+            Process::spawn(
+                (client.clone(), request),
+                |(mut client, request): (TcpStream, String), _: Mailbox<()>| {
+                    let _ = client.write_all(request.as_bytes());
+                },
+            );
+
+            let mut handled = 0;
+            super::serve(server, &config, None, |_, stream| {
+                handled += 1;
+                stream
+                    .respond(Response::build().body("ok").build())
+                    .unwrap()
+            });
+            handled
+        };
+
+        // a small body is skipped over, and the next request is served
+        assert_eq!(serve(1000), 2);
+        // a big one is not read, and the connection is closed instead
+        assert_eq!(serve(100 * 1024), 1);
+    }
+
+    #[lunatic::test]
+    fn test_transfer_encoding() {
+        let config = ServerConfig::default();
+
+        let response = send(
+            &config,
+            "POST / HTTP/1.1\r\nHost: a\r\nContent-Type: application/json\r\n\
+            Transfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n5\r\n[1,2]\r\n0\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\r\nConnection: close\r\n"));
+        assert!(response.ends_with("\r\n\r\n2"));
+
+        let response = send(
+            &config,
+            "POST / HTTP/1.1\r\nHost: a\r\nContent-Type: application/json\r\n\
+            Transfer-Encoding: gzip, chunked\r\n\r\n5\r\n[1,2]\r\n0\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 501 Not Implemented\r\n"));
+    }
+
+    #[lunatic::test]
+    fn test_timeouts() {
+        let config = ServerConfig::default()
+            .head_timeout(Duration::from_millis(100))
+            .body_timeout(Duration::from_millis(100));
+
+        assert_eq!(send(&config, ""), "");
+
+        let response = send(&config, "POST / HTTP/1.1\r\nHost: a\r\n");
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        assert!(response.contains("\r\nConnection: close\r\n"));
+
+        let response = send(
+            &config,
+            "POST / HTTP/1.1\r\nHost: a\r\nContent-Type: application/json\r\n\
+            Content-Length: 5\r\n\r\n[1",
+        );
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    }
+}
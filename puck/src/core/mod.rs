@@ -1,6 +1,12 @@
-//!
+//! The web server.
 
-use std::{io, mem};
+use std::{
+    cell::{Cell, RefCell},
+    fmt, io, mem,
+    net::SocketAddr,
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
 use lunatic::{
     net::{TcpListener, TcpStream, ToSocketAddrs},
@@ -9,113 +15,346 @@ use lunatic::{
 use serde::{de::DeserializeOwned, Serialize};
 
 use crate::{
-    response::encoder::Encoder,
+    error::{IntoResponse, PuckError},
+    response::{encoder::Encoder, status::StatusCode},
     ws::{self, websocket::WebSocket},
     Request, Response,
 };
 
-use self::router::Router;
+use self::{
+    router::{ErrorPage, ErrorPages, Handler, MakeRouter},
+    supervisor::{Event, Supervisor, WorkerArgs},
+    timeout::{ReadTimeout, TimedReader, TimedWriter},
+    tracker::{Admission, Tracker, Tracking},
+};
+
+pub use self::{
+    config::{ServerConfig, WhenFull},
+    supervisor::CrashReport,
+    tracker::{ConnectionStats, ShutdownHandle, StatsHandle},
+};
 
+pub mod config;
+mod connection;
 pub mod router;
+mod supervisor;
+pub(crate) mod timeout;
+mod tracker;
 
-///
-#[derive(Debug)]
+/// A web server, which serves requests made to the address it is bound to.
 pub struct Core<STATE> {
     state: STATE,
     listener: TcpListener,
+    config: ServerConfig,
+    tracking: Tracking,
+    /// Callbacks which are run once the server has shut down (see [Core::on_shutdown]).
+    shutdown_hooks: Vec<Box<dyn FnOnce()>>,
+    /// The process crashes are reported to (see [Core::report_crashes]).
+    reporter: Option<Process<CrashReport>>,
+}
+
+impl<STATE: fmt::Debug> fmt::Debug for Core<STATE> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Core")
+            .field("state", &self.state)
+            .field("listener", &self.listener)
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
 }
 
 impl<STATE> Core<STATE>
 where
     STATE: Clone + Serialize + DeserializeOwned,
 {
-    /// Bind
-    pub fn bind(addr: impl ToSocketAddrs, state: STATE) -> Result<Self, io::Error> {
+    /// Bind a new server to the provided address. Every handler will be given a copy of `state`.
+    ///
+    /// Requests which exceed the limits in `config` are rejected (see [ServerConfig]).
+    pub fn bind(
+        addr: impl ToSocketAddrs,
+        state: STATE,
+        config: ServerConfig,
+    ) -> Result<Self, io::Error> {
+        let listener = TcpListener::bind(addr)?;
+        let tracking = Tracking::start(listener.local_addr()?, config);
         Ok(Self {
             state,
-            listener: TcpListener::bind(addr)?,
+            listener,
+            config,
+            tracking,
+            shutdown_hooks: Vec::new(),
+            reporter: None,
         })
     }
 
-    /// Serves the current router, forever, on the bound address.
-    pub fn serve_router(self, router: Router<STATE>) {
-        let ints = router.as_ints();
+    /// The address the server is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.listener.local_addr()
+    }
 
-        loop {
-            if let Ok((stream, _)) = self.listener.accept() {
-                let _ = Process::spawn(
-                    (stream, ints.clone(), self.state.clone()),
-                    |(stream, ints, state), _: Mailbox<()>| {
-                        let router = Router::<STATE>::from_ints(ints);
-
-                        let req = if let Some(req) = Request::parse(stream.clone()).unwrap() {
-                            req
-                        } else {
-                            let stream = Stream::new(stream, false);
-                            // can't do much if this fails
-                            // todo: log it somehow
-                            let _ = stream.respond(crate::err_400());
-                            return;
-                        };
-
-                        let stream = Stream::new(stream, false);
-
-                        router.respond(req, stream, state);
-                    },
-                );
-            }
-        }
+    /// Returns a handle which can be used to shut the server down (see [ShutdownHandle]).
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.tracking.shutdown_handle()
+    }
+
+    /// Returns a handle which can be used to find out how many connections the server has open
+    /// (see [StatsHandle]).
+    pub fn stats_handle(&self) -> StatsHandle {
+        self.tracking.stats_handle()
+    }
+
+    /// Run `hook` once the server has shut down, just before [Core::serve_router] (or
+    /// [Core::for_each]) returns. Callbacks are run in the order they were added.
+    pub fn on_shutdown(mut self, hook: impl FnOnce() + 'static) -> Self {
+        self.shutdown_hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Send a [CrashReport] to `reporter` whenever a process serving a connection dies (e.g.
+    /// because a handler panicked), so that it can be passed on to an error reporting service.
+    ///
+    /// Crashes are logged whether or not there is a reporter.
+    ///
+    /// ```ignore
+    /// let reporter = Process::spawn((), |_, mailbox: Mailbox<CrashReport>| loop {
+    ///     let report = mailbox.receive();
+    ///     // ...send `report` somewhere...
+    /// });
+    /// Core::bind("localhost:8080", state, ServerConfig::default())?
+    ///     .report_crashes(reporter)
+    ///     .serve_router(App);
+    /// ```
+    pub fn report_crashes(mut self, reporter: Process<CrashReport>) -> Self {
+        self.reporter = Some(reporter);
+        self
+    }
+
+    /// Serves the router built by `make_router` on the bound address, until the server is shut
+    /// down (see [ShutdownHandle]).
+    ///
+    /// Each connection is handled in its own process (which builds its own copy of the router
+    /// with [MakeRouter::make_router]), and is kept open for as long as the client and the
+    /// handlers allow (see [Stream]). If a handler panics before it has started to respond, the
+    /// client is sent a `500 Internal Server Error` response, and the panic is logged (and
+    /// reported, see [Core::report_crashes]).
+    pub fn serve_router<MAKE>(self, make_router: MAKE)
+    where
+        MAKE: MakeRouter<STATE> + Clone,
+    {
+        let state = self.state.clone();
+        let config = self.config;
+        self.serve(
+            || (make_router.clone(), state.clone(), config),
+            supervise_router_connection::<STATE, MAKE>,
+        );
     }
 
-    /// Apply the provided function to every request.
+    /// Apply the provided handler to every request, until the server is shut down (see
+    /// [ShutdownHandle]).
     ///
-    /// This option gives you maximum flexibility.
+    /// This option gives you maximum flexibility. The handler is copied into the process which
+    /// serves each connection, so it must be serializable.
     ///
     /// note: if you choose this option, then the router will not be automatically applied to each
     /// request.
-    pub fn for_each(self, func: fn(Request, Stream, STATE) -> UsedStream) {
+    pub fn for_each<HANDLER>(self, handler: HANDLER)
+    where
+        HANDLER: Handler<STATE> + Serialize + DeserializeOwned + Clone,
+    {
+        let state = self.state.clone();
+        let config = self.config;
+        self.serve(
+            || (handler.clone(), state.clone(), config),
+            supervise_connection::<STATE, HANDLER>,
+        );
+    }
+
+    /// Accepts connections (serving each one in a process started with `entry`, unless there are
+    /// too many open already) until the server is shut down, then waits for the open connections
+    /// to be closed.
+    fn serve<C>(self, capture: impl Fn() -> C, entry: fn(ConnectionArgs<C>, Mailbox<Event>))
+    where
+        C: Serialize + DeserializeOwned,
+    {
         loop {
-            if let Ok((stream, _)) = self.listener.accept() {
-                let pointer = func as *const () as usize;
-
-                let _ = Process::spawn(
-                    (pointer, stream.clone(), self.state.clone()),
-                    |(pointer, stream, state), _: Mailbox<()>| {
-                        let reconstructed_func = pointer as *const ();
-                        let reconstructed_func = unsafe {
-                            mem::transmute::<*const (), fn(Request, Stream, STATE) -> UsedStream>(
-                                reconstructed_func,
-                            )
-                        };
-
-                        let req = Request::parse(stream.clone());
-
-                        match req {
-                            Ok(Some(req)) => {
-                                let stream = Stream::new(stream, false);
-
-                                // todo: keep-alive
-                                let _recovered_stream = (reconstructed_func)(req, stream, state);
-                            }
-                            _ => {
-                                todo!()
-                            }
-                        }
-                    },
-                );
+            let (stream, addr) = match self.listener.accept() {
+                Ok(accepted) => accepted,
+                Err(error) => {
+                    // errors such as running out of file descriptors tend to persist for a
+                    // while, so retrying straight away would only spin
+                    log::error!("failed to accept a connection: {}", error);
+                    lunatic::sleep(ACCEPT_BACKOFF);
+                    continue;
+                }
+            };
+            match self.tracking.admit(addr.ip()) {
+                Admission::Serve => {
+                    let supervisor = Process::spawn(
+                        (
+                            stream,
+                            capture(),
+                            self.tracking.tracker(),
+                            self.reporter.clone(),
+                        ),
+                        entry,
+                    );
+                    self.tracking.opened(supervisor, addr.ip());
+                }
+                // the tracker answers it in another process, so that it doesn't hold up the server
+                Admission::Reject => self.tracking.reject(stream),
+                Admission::Close => drop(stream),
+                Admission::Stop => break,
             }
         }
+        drop(self.listener);
+
+        self.tracking.wait();
+        for hook in self.shutdown_hooks {
+            (hook)();
+        }
     }
 }
+
+/// How long the server waits before accepting connections again after failing to accept one.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// How long the server waits for a client whose connection has been rejected to finish sending
+/// its request, before closing the connection (which, if there were unread data in it, could stop
+/// the client from receiving the response).
+const REJECT_LINGER: Duration = Duration::from_secs(1);
+
+/// Tells the client on the other end of `stream` that the server is too busy to serve it, then
+/// tells the tracker that it is done.
+fn reject((stream, config, tracker): (TcpStream, ServerConfig, Tracker), _: Mailbox<()>) {
+    let mut response = Response::error(StatusCode::SERVICE_UNAVAILABLE);
+    // `Retry-After` is in whole seconds, and zero would tell the client to retry straight away
+    let retry_after =
+        config.retry_after.as_secs() + u64::from(config.retry_after.subsec_nanos() > 0);
+    response
+        .headers
+        .insert_unchecked("Retry-After".to_string(), retry_after.max(1).to_string());
+
+    let mut reader = TimedReader::new(
+        stream.clone(),
+        ReadTimeout::Deadline(Instant::now() + REJECT_LINGER),
+    );
+    let mut stream = Stream::new(stream, false, 1);
+    // the response is short, so there is no need to wait as long as for other responses
+    stream.write_timeout = Some(config.write_timeout.min(REJECT_LINGER));
+    if stream.respond(response).is_ok() {
+        let _ = io::copy(&mut reader, &mut io::sink());
+    }
+    tracker.rejected();
+}
+
+/// The arguments a connection's supervisor is started with.
+type ConnectionArgs<C> = (TcpStream, C, Tracker, Option<Process<CrashReport>>);
+
+fn supervise_router_connection<STATE, MAKE>(
+    (stream, capture, tracker, reporter): ConnectionArgs<(MAKE, STATE, ServerConfig)>,
+    mailbox: Mailbox<Event>,
+) where
+    STATE: Clone + Serialize + DeserializeOwned,
+    MAKE: MakeRouter<STATE>,
+{
+    supervisor::supervise(
+        stream,
+        capture,
+        Some(tracker),
+        reporter,
+        mailbox,
+        serve_router_connection::<STATE, MAKE>,
+    );
+}
+
+fn serve_router_connection<STATE, MAKE>(
+    (stream, (make_router, state, config), supervisor): WorkerArgs<(MAKE, STATE, ServerConfig)>,
+    _: Mailbox<()>,
+) where
+    STATE: Clone,
+    MAKE: MakeRouter<STATE>,
+{
+    let router = make_router.make_router();
+    connection::serve(stream, &config, Some(supervisor), |req, stream| {
+        router.handle(req, stream, state.clone())
+    });
+}
+
+fn supervise_connection<STATE, HANDLER>(
+    (stream, capture, tracker, reporter): ConnectionArgs<(HANDLER, STATE, ServerConfig)>,
+    mailbox: Mailbox<Event>,
+) where
+    STATE: Clone + Serialize + DeserializeOwned,
+    HANDLER: Handler<STATE> + Serialize + DeserializeOwned,
+{
+    supervisor::supervise(
+        stream,
+        capture,
+        Some(tracker),
+        reporter,
+        mailbox,
+        serve_connection::<STATE, HANDLER>,
+    );
+}
+
+fn serve_connection<STATE, HANDLER>(
+    (stream, (handler, state, config), supervisor): WorkerArgs<(HANDLER, STATE, ServerConfig)>,
+    _: Mailbox<()>,
+) where
+    STATE: Clone,
+    HANDLER: Handler<STATE>,
+{
+    connection::serve(stream, &config, Some(supervisor), |req, stream| {
+        handler.handle(req, stream, state.clone())
+    });
+}
+
+type Hook = Box<dyn FnOnce(Response) -> Response>;
+
+/// A connection to a client, which a [Response] can be written to.
 ///
-#[derive(Debug)]
+/// Once a response has been sent, the connection is handed back (as a [UsedStream]) so that the
+/// next request the client sends over it can be served.
 pub struct Stream {
     stream: TcpStream,
     /// Can this stream be kept alive once it is returned to the web server?
     ///
-    /// If upgraded to a WebSocket connection, or the Content-Length is not
-    /// specified by the client, then this is not possible.
+    /// If the client asked for the connection to be closed, it is upgraded to a WebSocket
+    /// connection, or the length of the response is not known in advance, then this is not
+    /// possible.
     keep_alive: bool,
+    /// The minor version of HTTP/1 the client is speaking.
+    version: u8,
+    /// Whether the request being responded to is a `HEAD` request, in which case the body of the
+    /// response is not sent.
+    head: bool,
+    /// Functions which are applied to the response before it is sent (most recently added
+    /// first).
+    ///
+    /// This (like `error_pages` and `responded`) is shared with the copy of the stream which is
+    /// kept in case the handler returns an error (see [Stream::duplicate]).
+    response_hooks: Rc<RefCell<Vec<Hook>>>,
+    /// The error pages registered by the routers the request has passed through (see
+    /// [Router::error_page](router::Router::error_page)). Later pages take precedence.
+    error_pages: Rc<RefCell<ErrorPages>>,
+    /// Whether a response has been (or is being) sent, or the connection has been upgraded.
+    responded: Rc<Cell<bool>>,
+    /// The process which responds to the client if this one dies while handling the request.
+    supervisor: Option<Supervisor>,
+    /// How long writing each part of the response may take (see
+    /// [ServerConfig::write_timeout]).
+    write_timeout: Option<Duration>,
+}
+
+impl fmt::Debug for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Stream")
+            .field("stream", &self.stream)
+            .field("keep_alive", &self.keep_alive)
+            .field("version", &self.version)
+            .field("head", &self.head)
+            .finish_non_exhaustive()
+    }
 }
 
 /// An error encountered when trying to upgrade a WebSocket connection.
@@ -127,9 +366,100 @@ pub enum UpgradeError {
 }
 
 impl Stream {
-    // note: no keep_alive support for now!
-    fn new(stream: TcpStream, keep_alive: bool) -> Stream {
-        Self { stream, keep_alive }
+    pub(crate) fn new(stream: TcpStream, keep_alive: bool, version: u8) -> Stream {
+        Self {
+            stream,
+            keep_alive,
+            version,
+            head: false,
+            response_hooks: Rc::default(),
+            error_pages: Rc::default(),
+            responded: Rc::default(),
+            supervisor: None,
+            write_timeout: None,
+        }
+    }
+
+    /// Returns another handle to this stream, which can still be used to respond if the handler
+    /// which this stream is passed to returns an error.
+    pub(crate) fn duplicate(&self) -> Stream {
+        Self {
+            stream: self.stream.clone(),
+            keep_alive: self.keep_alive,
+            version: self.version,
+            head: self.head,
+            response_hooks: self.response_hooks.clone(),
+            error_pages: self.error_pages.clone(),
+            responded: self.responded.clone(),
+            supervisor: self.supervisor.clone(),
+            write_timeout: self.write_timeout,
+        }
+    }
+
+    pub(crate) fn add_error_pages(&self, pages: &[(StatusCode, Rc<ErrorPage>)]) {
+        self.error_pages.borrow_mut().extend_from_slice(pages);
+    }
+
+    /// The response which should be sent to report an error with the given status: the
+    /// error page registered for the status (see
+    /// [Router::error_page](router::Router::error_page)) if there is one, and otherwise
+    /// [Response::error].
+    pub fn error_response(&self, status: StatusCode) -> Response {
+        let page = self
+            .error_pages
+            .borrow()
+            .iter()
+            .rev()
+            .find(|(page_status, _)| *page_status == status)
+            .map(|(_, page)| page.clone());
+
+        match page {
+            Some(page) => (page)(status),
+            None => status.into_response(),
+        }
+    }
+
+    /// Respond to the client with the response for `error` (see [PuckError]).
+    ///
+    /// If a response has already been started, it is too late to send another one, so the
+    /// connection is closed instead.
+    pub(crate) fn respond_error(self, error: PuckError) -> UsedStream {
+        if let PuckError::Io(_) | PuckError::Other(_) = error {
+            log::error!("failed to handle a request: {}", error);
+        }
+
+        if self.responded.get() {
+            return UsedStream::empty();
+        }
+
+        let response = match error {
+            // the handler chose this response itself, so there is no error page to look up
+            PuckError::Response(_) => error.into_response(),
+            error => self.error_response(error.status()),
+        };
+        self.respond(response)
+            .unwrap_or_else(|_| UsedStream::empty())
+    }
+
+    /// Records that the response has been started, after which the supervisor no longer tries to
+    /// respond if this process dies.
+    fn start_response(&self) {
+        self.responded.set(true);
+        if let Some(supervisor) = &self.supervisor {
+            supervisor.notify(Event::Responding);
+        }
+    }
+
+    /// Apply `hook` to the response which is eventually sent over this stream, just before it is
+    /// sent.
+    ///
+    /// This is how middleware post-processes responses (see
+    /// [Middleware](router::Middleware)). If several hooks are added, the one added last is
+    /// applied first, so that middleware further out sees the response after the middleware
+    /// further in has processed it.
+    pub fn map_response(self, hook: impl FnOnce(Response) -> Response + 'static) -> Stream {
+        self.response_hooks.borrow_mut().push(Box::new(hook));
+        self
     }
 
     /// Upgrade
@@ -137,32 +467,92 @@ impl Stream {
         self.keep_alive = false;
 
         if !ws::should_upgrade(req) {
-            return Err(self.respond(crate::err_400()).unwrap());
+            let response = self.error_response(StatusCode::BAD_REQUEST);
+            return Err(self
+                .respond(response)
+                .unwrap_or_else(|_| UsedStream::empty()));
         }
 
+        self.start_response();
         if !ws::perform_upgrade(req, self.stream.clone()) {
             return Err(UsedStream::empty());
         }
+        if let Some(supervisor) = &self.supervisor {
+            supervisor.notify(Event::Upgraded);
+        }
 
         Ok(WebSocket::new(self.stream))
     }
 
-    /// Send a response
-    pub fn respond(self, response: Response) -> Result<UsedStream, io::Error> {
-        let mut enc = Encoder::new(response);
+    /// Send a response.
+    ///
+    /// The connection will be closed after the response has been sent if the response contains a
+    /// `Connection: close` header, or if the client cannot otherwise tell where the response ends.
+    pub fn respond(mut self, mut response: Response) -> Result<UsedStream, io::Error> {
+        self.start_response();
+
+        let hooks = mem::take(&mut *self.response_hooks.borrow_mut());
+        for hook in hooks.into_iter().rev() {
+            response = (hook)(response);
+        }
 
-        enc.write_tcp_stream(self.stream.clone())?;
+        self.prepare_connection_headers(&mut response);
+
+        let mut enc = Encoder::new(response)
+            .chunked(self.version > 0)
+            .head(self.head);
+
+        match self.write_timeout {
+            Some(timeout) => {
+                enc.write_tcp_stream(TimedWriter::new(self.stream.clone(), timeout))?
+            }
+            None => enc.write_tcp_stream(self.stream.clone())?,
+        }
 
         Ok(UsedStream {
             stream: Some(self.stream),
             keep_alive: self.keep_alive,
         })
     }
+
+    /// Decides whether the connection can be kept open once `response` has been sent, and sets
+    /// the `Connection` header to let the client know.
+    fn prepare_connection_headers(&mut self, response: &mut Response) {
+        let has_connection = response.headers.contains_key("connection");
+
+        if response
+            .headers
+            .get_list("connection")
+            .any(|token| token.eq_ignore_ascii_case("close"))
+        {
+            self.keep_alive = false;
+        }
+
+        let has_content_length = response.headers.contains_key("content-length");
+
+        // HTTP/1.0 clients don't understand chunked responses, so the only way they can tell
+        // where a response of unknown length ends is by the connection closing
+        if self.version == 0 && response.body.length.is_none() && !has_content_length {
+            self.keep_alive = false;
+        }
+
+        if !has_connection {
+            if !self.keep_alive {
+                response
+                    .headers
+                    .append_unchecked("Connection".to_string(), "close".to_string());
+            } else if self.version == 0 {
+                response
+                    .headers
+                    .append_unchecked("Connection".to_string(), "keep-alive".to_string());
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
 #[allow(unused)]
-///
+/// A connection which a response has been sent over (or which has been closed).
 pub struct UsedStream {
     pub(crate) stream: Option<TcpStream>,
     pub(crate) keep_alive: bool,
@@ -1,5 +1,8 @@
 //! Utilities for matching urls.
 
+use std::{collections::HashMap, str::FromStr};
+
+use percent_encoding::percent_decode_str;
 use url::Url;
 
 #[derive(Debug, Default)]
@@ -7,7 +10,7 @@ use url::Url;
 #[must_use]
 pub struct Match {
     segments: Vec<Segment>,
-    // todo: other things, e.g. query params
+    query: Vec<QueryParam>,
 }
 
 impl Match {
@@ -22,42 +25,101 @@ impl Match {
         self
     }
 
+    /// Add a constraint on the query string to the matcher.
+    pub fn query(mut self, param: QueryParam) -> Match {
+        self.query.push(param);
+        self
+    }
+
     /// Test if this matcher matches the url.
     pub fn does_match(&self, url: &Url) -> bool {
+        self.captures(url).is_some()
+    }
+
+    /// Test if this matcher matches the url, and if so return the values of the parameters which
+    /// it captured.
+    pub fn captures(&self, url: &Url) -> Option<Params> {
+        let mut params = Params::default();
+
         let mut expected_iter = self.segments.iter();
-        let mut actual_iter = if let Some(segments) = url.path_segments() {
-            segments
-        } else {
-            return false;
-        };
+        let mut actual_iter = url.path_segments()?;
 
         loop {
             let expected = if let Some(expected) = expected_iter.next() {
                 expected
+            } else if actual_iter.next().is_none() {
+                break;
             } else {
-                return actual_iter.next().is_none();
+                return None;
             };
 
-            let actual = if let Some(actual) = actual_iter.next() {
-                actual
-            } else {
-                return false;
-            };
+            if let Segment::Rest = expected {
+                params.rest = Some(
+                    actual_iter
+                        .by_ref()
+                        .map(decode)
+                        .collect::<Vec<_>>()
+                        .join("/"),
+                );
+                // `**` only makes sense as the last segment
+                if expected_iter.next().is_some() {
+                    return None;
+                }
+                break;
+            }
+
+            let actual = actual_iter.next()?;
 
             match expected {
                 Segment::Static(expected_path) => {
                     if *expected_path != actual {
-                        return false;
+                        return None;
                     }
                 }
-                Segment::Param => {}
+                Segment::Param => params.positional.push(decode(actual)),
                 Segment::IntParam => {
-                    if actual.parse::<i32>().is_err() {
-                        return false;
+                    if !is_integer(actual) {
+                        return None;
                     }
+                    params.positional.push(actual.to_string());
                 }
+                Segment::Named(name) => {
+                    let value = decode(actual);
+                    params.positional.push(value.clone());
+                    params.named.insert(name.to_string(), value);
+                }
+                Segment::NamedInt(name) => {
+                    if !is_integer(actual) {
+                        return None;
+                    }
+                    params.positional.push(actual.to_string());
+                    params.named.insert(name.to_string(), actual.to_string());
+                }
+                Segment::Rest => unreachable!(),
             }
         }
+
+        for expected in &self.query {
+            let name = expected.name();
+            let value = url
+                .query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned());
+
+            match (expected, value) {
+                (QueryParam::Optional(_), None) => continue,
+                (QueryParam::Int(_), Some(value)) if !is_integer(&value) => return None,
+                (QueryParam::Equals(_, expected), Some(value)) if *expected != value => {
+                    return None
+                }
+                (_, Some(value)) => {
+                    params.query.insert(name.to_string(), value);
+                }
+                (_, None) => return None,
+            }
+        }
+
+        Some(params)
     }
 }
 
@@ -70,6 +132,37 @@ pub enum Segment {
     Param,
     /// Any integer
     IntParam,
+    /// Anything, which is captured under the given name
+    Named(&'static str),
+    /// Any integer, which is captured under the given name
+    NamedInt(&'static str),
+    /// The rest of the path (zero or more segments). This can only be used as the last segment.
+    Rest,
+}
+
+#[derive(Debug)]
+/// A constraint on the query string of a URL. The values of the matched parameters are captured
+/// under their names.
+pub enum QueryParam {
+    /// The parameter must be present.
+    Present(&'static str),
+    /// The parameter must be present, and be an integer.
+    Int(&'static str),
+    /// The parameter must be present and have exactly this value.
+    Equals(&'static str, &'static str),
+    /// The parameter is captured if it is present, but the URL will match either way.
+    Optional(&'static str),
+}
+
+impl QueryParam {
+    fn name(&self) -> &'static str {
+        match self {
+            QueryParam::Present(name)
+            | QueryParam::Int(name)
+            | QueryParam::Equals(name, _)
+            | QueryParam::Optional(name) => name,
+        }
+    }
 }
 
 /// Syntactic sugar to construct a [Segment].
@@ -87,6 +180,96 @@ pub fn any_integer() -> Segment {
     Segment::IntParam
 }
 
+/// Syntactic sugar to construct a [Segment].
+pub fn param(name: &'static str) -> Segment {
+    Segment::Named(name)
+}
+
+/// Syntactic sugar to construct a [Segment].
+pub fn int_param(name: &'static str) -> Segment {
+    Segment::NamedInt(name)
+}
+
+/// Syntactic sugar to construct a [Segment] (this is the `**` segment).
+pub fn rest() -> Segment {
+    Segment::Rest
+}
+
+/// Syntactic sugar to construct a [QueryParam].
+pub fn query(name: &'static str) -> QueryParam {
+    QueryParam::Present(name)
+}
+
+/// Syntactic sugar to construct a [QueryParam].
+pub fn query_int(name: &'static str) -> QueryParam {
+    QueryParam::Int(name)
+}
+
+/// Syntactic sugar to construct a [QueryParam].
+pub fn query_equals(name: &'static str, value: &'static str) -> QueryParam {
+    QueryParam::Equals(name, value)
+}
+
+/// Syntactic sugar to construct a [QueryParam].
+pub fn optional_query(name: &'static str) -> QueryParam {
+    QueryParam::Optional(name)
+}
+
+/// The values captured when a [Match] matched a URL.
+///
+/// Path segments are percent-decoded before they are captured.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Params {
+    positional: Vec<String>,
+    named: HashMap<String, String>,
+    query: HashMap<String, String>,
+    rest: Option<String>,
+}
+
+impl Params {
+    /// Parse the path parameter with the given name (captured by [Segment::Named] or
+    /// [Segment::NamedInt]) into a `T`, returning `None` if it was not captured or could not be
+    /// parsed.
+    pub fn get<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.get_str(name).and_then(|value| value.parse().ok())
+    }
+
+    /// Get the path parameter with the given name.
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        self.named.get(name).map(String::as_str)
+    }
+
+    /// Parse the `n`th (counting from zero) non-static segment of the path into a `T`.
+    pub fn nth<T: FromStr>(&self, n: usize) -> Option<T> {
+        self.positional.get(n).and_then(|value| value.parse().ok())
+    }
+
+    /// Parse the query parameter with the given name into a `T`.
+    pub fn query<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.query_str(name).and_then(|value| value.parse().ok())
+    }
+
+    /// Get the query parameter with the given name.
+    pub fn query_str(&self, name: &str) -> Option<&str> {
+        self.query.get(name).map(String::as_str)
+    }
+
+    /// The part of the path matched by [Segment::Rest] (the segments of which are separated by
+    /// `/`), if any.
+    pub fn rest(&self) -> Option<&str> {
+        self.rest.as_deref()
+    }
+}
+
+/// Integer segments may be either an `i64` or a `u64`.
+fn is_integer(segment: &str) -> bool {
+    segment.parse::<i64>().is_ok() || segment.parse::<u64>().is_ok()
+}
+
+fn decode(segment: &str) -> String {
+    percent_decode_str(segment).decode_utf8_lossy().into_owned()
+}
+
 #[cfg(test)]
 mod test {
     use std::str::FromStr;
@@ -108,4 +291,40 @@ mod test {
             &Url::from_str("https://example.com/home/name/someone/page/twelve").unwrap()
         ));
     }
+
+    #[lunatic::test]
+    fn test_captures() {
+        let matcher = Match::new()
+            .at(path("users"))
+            .at(param("name"))
+            .at(int_param("id"))
+            .at(rest())
+            .query(query_int("page"))
+            .query(optional_query("sort"));
+
+        let params = matcher
+            .captures(
+                &Url::from_str("https://example.com/users/jane%20doe/-12/files/a.txt?page=3")
+                    .unwrap(),
+            )
+            .unwrap();
+        assert_eq!(params.get_str("name"), Some("jane doe"));
+        assert_eq!(params.get::<i64>("id"), Some(-12));
+        assert_eq!(params.get::<u64>("id"), None);
+        assert_eq!(params.nth::<i64>(1), Some(-12));
+        assert_eq!(params.rest(), Some("files/a.txt"));
+        assert_eq!(params.query::<u32>("page"), Some(3));
+        assert_eq!(params.query_str("sort"), None);
+
+        assert!(!matcher
+            .does_match(&Url::from_str("https://example.com/users/jane/12?page=three").unwrap()));
+        assert!(!matcher.does_match(&Url::from_str("https://example.com/users/jane/12").unwrap()));
+        assert_eq!(
+            matcher
+                .captures(&Url::from_str("https://example.com/users/jane/12?page=1").unwrap())
+                .unwrap()
+                .rest(),
+            Some("")
+        );
+    }
 }
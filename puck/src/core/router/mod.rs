@@ -1,21 +1,174 @@
 //! A router.
-use std::{fmt, mem};
+use std::{fmt, rc::Rc};
 
-use lunatic::{net::TcpListener, Mailbox, Process};
 use serde::{de::DeserializeOwned, Serialize};
 
-use crate::Request;
+use crate::{error::PuckError, request::Method, response::status::StatusCode, Request, Response};
+
+use self::match_url::{Match, Params};
 
 use super::{Stream, UsedStream};
 
 pub mod match_url;
 
+/// Something which can respond to a request.
+///
+/// This is implemented for every function or closure with the signature
+/// `Fn(Request, Stream, STATE) -> UsedStream` or
+/// `Fn(Request, Stream, STATE) -> Result<UsedStream, PuckError>` (see [HandlerOutput]), as well
+/// as for [Router] (so routers can be used anywhere a handler can). Implement it yourself for
+/// handlers which need to carry configuration of their own.
+pub trait Handler<STATE> {
+    /// Respond to the request.
+    fn handle(&self, req: Request, stream: Stream, state: STATE) -> UsedStream;
+}
+
+impl<STATE, F, R> Handler<STATE> for F
+where
+    F: Fn(Request, Stream, STATE) -> R,
+    R: HandlerOutput,
+{
+    fn handle(&self, req: Request, stream: Stream, state: STATE) -> UsedStream {
+        let fallback = stream.duplicate();
+        (self)(req, stream, state).into_used_stream(fallback)
+    }
+}
+
+/// What a handler function can return.
+///
+/// If a handler returns an error, the client is sent the response for the error (see
+/// [PuckError]), unless the handler had already started responding (in which case the
+/// connection is closed).
+pub trait HandlerOutput {
+    /// Finish handling the request. `stream` is another handle to the stream which was passed to
+    /// the handler, which can be used to report an error.
+    fn into_used_stream(self, stream: Stream) -> UsedStream;
+}
+
+impl HandlerOutput for UsedStream {
+    fn into_used_stream(self, _: Stream) -> UsedStream {
+        self
+    }
+}
+
+impl HandlerOutput for Result<UsedStream, PuckError> {
+    fn into_used_stream(self, stream: Stream) -> UsedStream {
+        self.unwrap_or_else(|error| stream.respond_error(error))
+    }
+}
+
+/// Code which runs around a [Handler] (e.g. for logging, authentication or adding headers to
+/// every response).
+///
+/// Middleware is given the request along with `next` (the handler it wraps). It can respond to
+/// the request itself (in which case `next` is never run), or it can pass the request on by
+/// calling `next.handle`. To post-process the response which `next` sends, add a hook with
+/// [Stream::map_response] before passing the stream on.
+///
+/// This is implemented for every function or closure with the signature
+/// `Fn(Request, Stream, STATE, &dyn Handler<STATE>) -> UsedStream` (or which returns a
+/// `Result<UsedStream, PuckError>`, as with [Handler]).
+///
+/// ```ignore
+/// router.wrap(|req, stream, state, next: &dyn Handler<_>| {
+///     let stream = stream.map_response(|mut response| {
+///         response.headers_mut().insert("X-Frame-Options".into(), "DENY".into());
+///         response
+///     });
+///     next.handle(req, stream, state)
+/// })
+/// ```
+pub trait Middleware<STATE> {
+    /// Handle the request, optionally passing it on to `next`.
+    fn handle(
+        &self,
+        req: Request,
+        stream: Stream,
+        state: STATE,
+        next: &dyn Handler<STATE>,
+    ) -> UsedStream;
+}
+
+impl<STATE, F, R> Middleware<STATE> for F
+where
+    F: Fn(Request, Stream, STATE, &dyn Handler<STATE>) -> R,
+    R: HandlerOutput,
+{
+    fn handle(
+        &self,
+        req: Request,
+        stream: Stream,
+        state: STATE,
+        next: &dyn Handler<STATE>,
+    ) -> UsedStream {
+        let fallback = stream.duplicate();
+        (self)(req, stream, state, next).into_used_stream(fallback)
+    }
+}
+
+/// A [Handler] wrapped in a series of [Middleware], the first of which is the outermost.
+struct Wrapped<'a, STATE> {
+    middleware: &'a [Box<dyn Middleware<STATE>>],
+    handler: &'a dyn Handler<STATE>,
+}
+
+impl<'a, STATE> Handler<STATE> for Wrapped<'a, STATE> {
+    fn handle(&self, req: Request, stream: Stream, state: STATE) -> UsedStream {
+        match self.middleware.split_first() {
+            Some((first, rest)) => first.handle(
+                req,
+                stream,
+                state,
+                &Wrapped {
+                    middleware: rest,
+                    handler: self.handler,
+                },
+            ),
+            None => self.handler.handle(req, stream, state),
+        }
+    }
+}
+
+/// Builds the [Router] which requests are served with.
+///
+/// Every connection is served in its own lunatic process, and processes do not share memory, so
+/// a [Router] (the handlers of which may be arbitrary closures) cannot be copied from one
+/// process to another. Instead, this value is sent to the process serving each connection, and
+/// is used to build the [Router] there. Any configuration the handlers need (e.g. the address
+/// of a database) can be stored in it and captured by the handlers.
+pub trait MakeRouter<STATE>: Serialize + DeserializeOwned {
+    /// Build the router.
+    fn make_router(&self) -> Router<STATE>;
+}
+
 #[allow(missing_docs)]
-#[derive(Copy, Clone)]
 #[must_use]
 pub struct Route<STATE> {
-    matcher: fn(&Request) -> bool,
-    handler: fn(Request, Stream, STATE) -> UsedStream,
+    /// The method this route accepts, if it was constructed with one. Routes with a method take
+    /// part in the router's automatic `405`, `HEAD` and `OPTIONS` handling.
+    method: Option<Method>,
+    matcher: Matcher,
+    handler: Box<dyn Handler<STATE>>,
+    middleware: Vec<Box<dyn Middleware<STATE>>>,
+}
+
+type TestFn = dyn Fn(&Request) -> bool;
+type CaptureFn = dyn Fn(&Request) -> Option<Params>;
+
+enum Matcher {
+    Test(Box<TestFn>),
+    Capture(Box<CaptureFn>),
+    Url(Match),
+}
+
+impl Matcher {
+    fn captures(&self, req: &Request) -> Option<Params> {
+        match self {
+            Matcher::Test(matcher) => (matcher)(req).then(Params::default),
+            Matcher::Capture(matcher) => (matcher)(req),
+            Matcher::Url(matcher) => matcher.captures(req.url()),
+        }
+    }
 }
 
 impl<STATE> fmt::Debug for Route<STATE> {
@@ -28,26 +181,161 @@ impl<STATE> Route<STATE> {
     /// Constructs a new `Route`.
     ///
     /// A substantially nicer API will come.
-    pub fn new(
-        matcher: fn(&Request) -> bool,
-        handler: fn(Request, Stream, STATE) -> UsedStream,
+    pub fn new<R: HandlerOutput>(
+        matcher: impl Fn(&Request) -> bool + 'static,
+        handler: impl Fn(Request, Stream, STATE) -> R + 'static,
+    ) -> Route<STATE> {
+        Route::with_handler(matcher, handler)
+    }
+
+    /// Constructs a new `Route`, which passes the requests it matches to the provided
+    /// [Handler].
+    pub fn with_handler(
+        matcher: impl Fn(&Request) -> bool + 'static,
+        handler: impl Handler<STATE> + 'static,
+    ) -> Route<STATE> {
+        Route {
+            method: None,
+            matcher: Matcher::Test(Box::new(matcher)),
+            handler: Box::new(handler),
+            middleware: vec![],
+        }
+    }
+
+    /// Constructs a new `Route`, the matcher of which also captures parameters from the request
+    /// (usually by calling [Match::captures](match_url::Match::captures)). These are then
+    /// available to the handler through [Request::params].
+    ///
+    /// ```ignore
+    /// Route::capturing(
+    ///     |req| Match::new().at(path("read")).at(int_param("n")).captures(req.url()),
+    ///     |req, stream, state| {
+    ///         let n = req.params().get::<usize>("n").unwrap();
+    ///         // ...
+    ///     },
+    /// )
+    /// ```
+    pub fn capturing<R: HandlerOutput>(
+        matcher: impl Fn(&Request) -> Option<Params> + 'static,
+        handler: impl Fn(Request, Stream, STATE) -> R + 'static,
+    ) -> Route<STATE> {
+        Route {
+            method: None,
+            matcher: Matcher::Capture(Box::new(matcher)),
+            handler: Box::new(handler),
+            middleware: vec![],
+        }
+    }
+
+    /// Constructs a new `Route`, which matches requests made with the given method to URLs
+    /// matched by `matcher`. The parameters captured by `matcher` are available to the handler
+    /// through [Request::params].
+    ///
+    /// If the method is `GET`, then this route will also match `HEAD` requests.
+    pub fn matching<R: HandlerOutput>(
+        method: Method,
+        matcher: Match,
+        handler: impl Fn(Request, Stream, STATE) -> R + 'static,
     ) -> Route<STATE> {
-        Route { matcher, handler }
+        Route {
+            method: Some(method),
+            matcher: Matcher::Url(matcher),
+            handler: Box::new(handler),
+            middleware: vec![],
+        }
+    }
+
+    /// Wrap this route's handler in `middleware`, which only runs for requests which this route
+    /// matches. If this is called several times, the middleware added first runs first.
+    pub fn wrap(mut self, middleware: impl Middleware<STATE> + 'static) -> Route<STATE> {
+        self.middleware.push(Box::new(middleware));
+        self
     }
 }
 
 /// A [Router] provides an easy way to match different types of HTTP request and handle them
 /// differently.
-#[derive(Debug, Clone, Default)]
+///
+/// Requests are passed to the first route which matches them. If none do, then:
+/// - if the URL matched a route constructed with a method (e.g. using [Router::get]), but the
+///   request method did not, `OPTIONS` requests receive a `204` response (and any other request a
+///   `405` response) with an `Allow` header listing the methods which would have matched
+/// - otherwise, the request is passed to the fallback handler (see [Router::fallback]), which
+///   by default responds with [Response::not_found].
+///
+/// Middleware added to the router with [Router::wrap] runs for every request, before the request
+/// is routed.
+///
+/// The pages sent when something goes wrong (e.g. the `404` page) can be customised with
+/// [Router::error_page].
 #[must_use]
 pub struct Router<STATE> {
     routes: Vec<Route<STATE>>,
+    fallback: Option<Box<dyn Handler<STATE>>>,
+    middleware: Vec<Box<dyn Middleware<STATE>>>,
+    error_pages: ErrorPages,
 }
 
-impl<STATE: Serialize + DeserializeOwned + Clone> Router<STATE> {
+/// Builds the response sent to report an error with the given status.
+pub(crate) type ErrorPage = dyn Fn(StatusCode) -> Response;
+
+/// Error pages, along with the status each is for.
+pub(crate) type ErrorPages = Vec<(StatusCode, Rc<ErrorPage>)>;
+
+impl<STATE> fmt::Debug for Router<STATE> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Router")
+            .field("routes", &self.routes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<STATE> Default for Router<STATE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<STATE> Router<STATE> {
     /// Constructs a new [Router].
     pub fn new() -> Router<STATE> {
-        Router { routes: vec![] }
+        Router {
+            routes: vec![],
+            fallback: None,
+            middleware: vec![],
+            error_pages: vec![],
+        }
+    }
+
+    /// Wrap every request this router handles in `middleware`. If this is called several times,
+    /// the middleware added first runs first.
+    pub fn wrap(mut self, middleware: impl Middleware<STATE> + 'static) -> Router<STATE> {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Use `page` to build the response sent to report an error with the given status, in place
+    /// of [Response::error]. This applies to the errors the router reports itself (e.g. `404`
+    /// when no route matches), as well as to errors returned by handlers (see [PuckError]).
+    ///
+    /// When routers are nested, the pages of the inner router take precedence.
+    ///
+    /// ```ignore
+    /// router.error_page(StatusCode::NOT_FOUND, |status| {
+    ///     Response::build()
+    ///         .status_code(status)
+    ///         .header("Content-Type", "text/html")
+    ///         .body(include_str!("404.html"))
+    ///         .build()
+    /// })
+    /// ```
+    pub fn error_page(
+        mut self,
+        status: StatusCode,
+        page: impl Fn(StatusCode) -> Response + 'static,
+    ) -> Router<STATE> {
+        self.error_pages.push((status, Rc::new(page)));
+        self
     }
 
     /// Add a route to the router.
@@ -56,70 +344,498 @@ impl<STATE: Serialize + DeserializeOwned + Clone> Router<STATE> {
         self
     }
 
-    /// Converts the router into a series of integers.
-    pub(crate) fn as_ints(&self) -> Vec<(usize, usize)> {
-        self.routes
-            .iter()
-            .map(|route| {
-                (
-                    route.matcher as *const () as usize,
-                    route.handler as *const () as usize,
-                )
-            })
-            .collect()
-    }
-
-    /// Reconstructs the router from `Router::as_ints`. Panics if the data is not in a valid form.
-    pub(crate) fn from_ints(ints: Vec<(usize, usize)>) -> Router<STATE> {
-        let routes = ints
-            .iter()
-            .map(|(matcher, handler)| Route {
-                matcher: {
-                    unsafe {
-                        let pointer = *matcher as *const ();
-                        mem::transmute::<*const (), fn(&Request) -> bool>(pointer)
-                    }
-                },
-                handler: {
-                    unsafe {
-                        let pointer = *handler as *const ();
-                        mem::transmute::<*const (), fn(Request, Stream, STATE) -> UsedStream>(
-                            pointer,
-                        )
-                    }
-                },
-            })
-            .collect::<Vec<_>>();
-        Router { routes }
+    /// Mount `router` at `prefix`, so that it handles every request for `prefix` or a path
+    /// beneath it.
+    ///
+    /// The prefix is removed from the request's URL before it is passed to `router`, so its
+    /// routes match paths relative to where it is mounted (e.g. a route for `/users` in a router
+    /// nested at `/api` handles requests for `/api/users`). The prefix is available to handlers
+    /// through [Request::base_path].
+    ///
+    /// Requests pass through this router's middleware before reaching the nested router (and
+    /// then through the nested router's own middleware). Requests under `prefix` which none of
+    /// the nested router's routes match are handled by the nested router's fallback.
+    ///
+    /// ```ignore
+    /// Router::new()
+    ///     .nest("/api", Router::new().get(Match::new().at(path("users")), users))
+    ///     .get(Match::new().at(path("")), index)
+    /// ```
+    pub fn nest(self, prefix: &str, router: Router<STATE>) -> Router<STATE>
+    where
+        STATE: 'static,
+    {
+        self.mount(prefix, router)
     }
 
-    /// Runs the router forever on the provided port.
-    pub fn run(self, listener: TcpListener, state: STATE) {
-        loop {
-            let stream = if let Ok((stream, _addr)) = listener.accept() {
-                stream
+    /// Mount `handler` at `prefix`, so that it handles every request for `prefix` or a path
+    /// beneath it (e.g. to serve a directory of files with [ServeDir](crate::fs::ServeDir)).
+    ///
+    /// As with [Router::nest], the prefix is removed from the request's URL before it is passed to
+    /// `handler`.
+    pub fn mount(self, prefix: &str, handler: impl Handler<STATE> + 'static) -> Router<STATE>
+    where
+        STATE: 'static,
+    {
+        let prefix = prefix.trim_end_matches('/').to_string();
+        let matcher = {
+            let prefix = prefix.clone();
+            move |req: &Request| strip_prefix(req.url().path(), &prefix).is_some()
+        };
+        self.route(Route::with_handler(
+            matcher,
+            Nested {
+                prefix,
+                handler: Box::new(handler),
+            },
+        ))
+    }
+
+    /// Add a route for `GET` (and `HEAD`) requests to URLs matched by `matcher`.
+    pub fn get<R: HandlerOutput>(
+        self,
+        matcher: Match,
+        handler: impl Fn(Request, Stream, STATE) -> R + 'static,
+    ) -> Router<STATE> {
+        self.route(Route::matching(Method::Get, matcher, handler))
+    }
+
+    /// Add a route for `POST` requests to URLs matched by `matcher`.
+    pub fn post<R: HandlerOutput>(
+        self,
+        matcher: Match,
+        handler: impl Fn(Request, Stream, STATE) -> R + 'static,
+    ) -> Router<STATE> {
+        self.route(Route::matching(Method::Post, matcher, handler))
+    }
+
+    /// Add a route for `PUT` requests to URLs matched by `matcher`.
+    pub fn put<R: HandlerOutput>(
+        self,
+        matcher: Match,
+        handler: impl Fn(Request, Stream, STATE) -> R + 'static,
+    ) -> Router<STATE> {
+        self.route(Route::matching(Method::Put, matcher, handler))
+    }
+
+    /// Add a route for `DELETE` requests to URLs matched by `matcher`.
+    pub fn delete<R: HandlerOutput>(
+        self,
+        matcher: Match,
+        handler: impl Fn(Request, Stream, STATE) -> R + 'static,
+    ) -> Router<STATE> {
+        self.route(Route::matching(Method::Delete, matcher, handler))
+    }
+
+    /// Add a route for `PATCH` requests to URLs matched by `matcher`.
+    pub fn patch<R: HandlerOutput>(
+        self,
+        matcher: Match,
+        handler: impl Fn(Request, Stream, STATE) -> R + 'static,
+    ) -> Router<STATE> {
+        self.route(Route::matching(Method::Patch, matcher, handler))
+    }
+
+    /// Set the handler for requests which no route matches. By default, these receive a
+    /// [Response::not_found] response.
+    pub fn fallback<R: HandlerOutput>(
+        mut self,
+        handler: impl Fn(Request, Stream, STATE) -> R + 'static,
+    ) -> Router<STATE> {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    /// Passes the request through the router's middleware, and then on to the first route which
+    /// matches it (see [Router] for what happens if none do).
+    pub(crate) fn respond(&self, req: Request, stream: Stream, state: STATE) -> UsedStream {
+        stream.add_error_pages(&self.error_pages);
+        Wrapped {
+            middleware: &self.middleware,
+            handler: &Dispatch(self),
+        }
+        .handle(req, stream, state)
+    }
+
+    fn dispatch(&self, mut req: Request, stream: Stream, state: STATE) -> UsedStream {
+        let mut allowed = Vec::new();
+
+        for route in &self.routes {
+            let params = if let Some(params) = route.matcher.captures(&req) {
+                params
             } else {
                 continue;
             };
 
-            let _ = Process::spawn(
-                (self.as_ints(), stream, state.clone()),
-                |(ints, stream, state), _: Mailbox<()>| {
-                    if let Ok(Some(req)) = Request::parse(stream.clone()) {
-                        let router = Router::<STATE>::from_ints(ints);
-                        router.respond(req, Stream::new(stream, false), state);
+            if let Some(method) = &route.method {
+                let accepts = method == req.method()
+                    || (method == &Method::Get && req.method() == &Method::Head);
+                if !accepts {
+                    if !allowed.contains(method) {
+                        allowed.push(method.clone());
                     }
-                },
-            );
+                    continue;
+                }
+            }
+
+            req.params = params;
+            return Wrapped {
+                middleware: &route.middleware,
+                handler: &*route.handler,
+            }
+            .handle(req, stream, state);
         }
-    }
 
-    pub(crate) fn respond(&self, req: Request, stream: Stream, state: STATE) {
-        for route in &self.routes {
-            if (route.matcher)(&req) {
-                (route.handler)(req, stream, state);
-                return;
+        if !allowed.is_empty() {
+            return respond_not_allowed(&req, stream, allowed);
+        }
+
+        match &self.fallback {
+            Some(fallback) => fallback.handle(req, stream, state),
+            None => {
+                let response = stream.error_response(StatusCode::NOT_FOUND);
+                stream
+                    .respond(response)
+                    .unwrap_or_else(|_| UsedStream::empty())
             }
         }
     }
 }
+
+/// A handler mounted at a path prefix by [Router::mount] (or [Router::nest]).
+struct Nested<STATE> {
+    /// The prefix, without a trailing slash.
+    prefix: String,
+    handler: Box<dyn Handler<STATE>>,
+}
+
+impl<STATE> Handler<STATE> for Nested<STATE> {
+    fn handle(&self, mut req: Request, stream: Stream, state: STATE) -> UsedStream {
+        let path = match strip_prefix(req.url.path(), &self.prefix) {
+            Some("") => "/".to_string(),
+            Some(path) => path.to_string(),
+            None => return self.handler.handle(req, stream, state),
+        };
+        req.url.set_path(&path);
+        req.base.push_str(&self.prefix);
+        self.handler.handle(req, stream, state)
+    }
+}
+
+/// Returns the rest of `path` if it is `prefix` or a path beneath `prefix` (which must not end in
+/// a slash).
+fn strip_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    path.strip_prefix(prefix)
+        .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Routes requests, once the router's middleware has run.
+struct Dispatch<'a, STATE>(&'a Router<STATE>);
+
+impl<'a, STATE> Handler<STATE> for Dispatch<'a, STATE> {
+    fn handle(&self, req: Request, stream: Stream, state: STATE) -> UsedStream {
+        self.0.dispatch(req, stream, state)
+    }
+}
+
+/// Responds to a request for a URL which exists, but not with the method that was requested.
+fn respond_not_allowed(req: &Request, stream: Stream, mut allowed: Vec<Method>) -> UsedStream {
+    if allowed.contains(&Method::Get) && !allowed.contains(&Method::Head) {
+        allowed.push(Method::Head);
+    }
+    if !allowed.contains(&Method::Options) {
+        allowed.push(Method::Options);
+    }
+
+    let allow = allowed
+        .iter()
+        .map(Method::as_str)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut response = if req.method() == &Method::Options {
+        Response::build()
+            .status_code(StatusCode::NO_CONTENT)
+            .build()
+    } else {
+        stream.error_response(StatusCode::METHOD_NOT_ALLOWED)
+    };
+    response
+        .headers
+        .insert_unchecked("Allow".to_string(), allow);
+
+    stream
+        .respond(response)
+        .unwrap_or_else(|_| UsedStream::empty())
+}
+
+impl<STATE> Handler<STATE> for Router<STATE> {
+    fn handle(&self, req: Request, stream: Stream, state: STATE) -> UsedStream {
+        self.respond(req, stream, state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+
+    use lunatic::net::{TcpListener, TcpStream};
+
+    use super::{
+        match_url::{int_param, path},
+        *,
+    };
+    use crate::core::{connection, ServerConfig};
+
+    /// Sends the raw request to the router over a real connection, and returns the raw response.
+    fn send(router: &Router<()>, request: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(request.as_bytes()).unwrap();
+
+        let (server, _) = listener.accept().unwrap();
+        connection::serve(server, &ServerConfig::default(), None, |req, stream| {
+            router.handle(req, stream, ())
+        });
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    fn router() -> Router<()> {
+        Router::new()
+            .get(
+                Match::new().at(path("item")).at(int_param("id")),
+                |req, stream, _| {
+                    let id = req.params().get::<u64>("id").unwrap();
+                    stream
+                        .respond(Response::build().body(format!("item {}", id)).build())
+                        .unwrap()
+                },
+            )
+            .post(
+                Match::new().at(path("item")).at(int_param("id")),
+                |_, stream, _| stream.respond(Response::build().build()).unwrap(),
+            )
+    }
+
+    #[lunatic::test]
+    fn test_get_and_head() {
+        let router = router();
+
+        let response = send(
+            &router,
+            "GET /item/7 HTTP/1.1\r\nHost: example.com\r\n\r\n\
+            HEAD /item/8 HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n",
+        );
+        assert_eq!(
+            response,
+            "HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nitem 7\
+            HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 6\r\n\r\n"
+        );
+    }
+
+    #[lunatic::test]
+    fn test_method_not_allowed_and_options() {
+        let router = router();
+
+        let response = send(
+            &router,
+            "DELETE /item/7 HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(response.contains("\r\nAllow: GET, POST, HEAD, OPTIONS\r\n"));
+
+        let response = send(
+            &router,
+            "OPTIONS /item/7 HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n",
+        );
+        assert_eq!(
+            response,
+            "HTTP/1.1 204 No Content\r\nAllow: GET, POST, HEAD, OPTIONS\r\nConnection: close\r\n\r\n"
+        );
+    }
+
+    #[lunatic::test]
+    fn test_fallback() {
+        let response = send(
+            &router(),
+            "GET /nothing HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let response = send(
+            &router().fallback(|_, stream, _| {
+                stream
+                    .respond(Response::build().status(410, "gone").build())
+                    .unwrap()
+            }),
+            "GET /nothing HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 410 gone\r\n"));
+    }
+
+    #[lunatic::test]
+    fn test_errors() {
+        let router = Router::new()
+            .get(Match::new().at(path("gone")), |_, _, _| {
+                Err(StatusCode::GONE.into())
+            })
+            .post(Match::new().at(path("double")), |mut req, stream, _| {
+                let n: u64 = req.take_body().json()?;
+                Ok(stream.respond(Response::build().body((n * 2).to_string()).build())?)
+            })
+            .error_page(StatusCode::NOT_FOUND, |status| {
+                Response::build()
+                    .status_code(status)
+                    .body("nothing here")
+                    .build()
+            })
+            .error_page(StatusCode::GONE, |status| {
+                Response::build()
+                    .status_code(status)
+                    .body("long gone")
+                    .build()
+            });
+
+        let response = send(
+            &router,
+            "GET /gone HTTP/1.1\r\nHost: example.com\r\n\r\n\
+            GET /nothing HTTP/1.1\r\nHost: example.com\r\n\r\n\
+            POST /double HTTP/1.1\r\nHost: example.com\r\nContent-Type: application/json\r\n\
+            Content-Length: 2\r\n\r\n21\
+            POST /double HTTP/1.1\r\nHost: example.com\r\nContent-Type: application/json\r\n\
+            Content-Length: 3\r\nConnection: close\r\n\r\n{}!",
+        );
+        assert_eq!(
+            response,
+            "HTTP/1.1 410 Gone\r\nContent-Length: 9\r\n\r\nlong gone\
+            HTTP/1.1 404 Not Found\r\nContent-Length: 12\r\n\r\nnothing here\
+            HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n42\
+            HTTP/1.1 400 Bad Request\r\nContent-Type: text/html;charset=utf-8\r\n\
+            Connection: close\r\nContent-Length: 24\r\n\r\n<h1>400 Bad Request</h1>"
+        );
+    }
+
+    #[lunatic::test]
+    fn test_middleware() {
+        let router = router()
+            .wrap(|req, stream: Stream, state, next: &dyn Handler<()>| {
+                let stream = stream.map_response(|mut response| {
+                    response.headers_mut().insert("X-Outer", "1").unwrap();
+                    response
+                });
+                next.handle(req, stream, state)
+            })
+            .wrap(
+                |req: Request, stream: Stream, state, next: &dyn Handler<()>| {
+                    if req.headers().contains_key("Authorization") {
+                        next.handle(req, stream, state)
+                    } else {
+                        stream
+                            .respond(Response::build().status(401, "unauthorized").build())
+                            .unwrap()
+                    }
+                },
+            );
+
+        let response = send(
+            &router,
+            "GET /item/7 HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n",
+        );
+        assert_eq!(
+            response,
+            "HTTP/1.1 401 unauthorized\r\nX-Outer: 1\r\nConnection: close\r\nContent-Length: 0\r\n\r\n"
+        );
+
+        let response = send(
+            &router,
+            "GET /nothing HTTP/1.1\r\nHost: example.com\r\nAuthorization: yes\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.contains("\r\nX-Outer: 1\r\n"));
+    }
+
+    #[lunatic::test]
+    fn test_route_middleware() {
+        let router = Router::new()
+            .route(
+                Route::matching(
+                    Method::Get,
+                    Match::new().at(path("admin")),
+                    |_, stream: Stream, _| stream.respond(Response::build().build()).unwrap(),
+                )
+                .wrap(|_, stream: Stream, _, _: &dyn Handler<()>| {
+                    stream
+                        .respond(Response::build().status(403, "forbidden").build())
+                        .unwrap()
+                }),
+            )
+            .get(Match::new().at(path("public")), |_, stream, _| {
+                stream.respond(Response::build().build()).unwrap()
+            });
+
+        let response = send(
+            &router,
+            "GET /admin HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 403 forbidden\r\n"));
+
+        let response = send(
+            &router,
+            "GET /public HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[lunatic::test]
+    fn test_nest() {
+        let api = Router::new()
+            .get(Match::new().at(path("")), |req, stream, _| {
+                let body = format!("api index at {}", req.base_path());
+                stream
+                    .respond(Response::build().body(body).build())
+                    .unwrap()
+            })
+            .get(Match::new().at(path("users")), |req, stream, _| {
+                let body = format!("{}{}", req.base_path(), req.url().path());
+                stream
+                    .respond(Response::build().body(body).build())
+                    .unwrap()
+            });
+        let router = Router::new()
+            .wrap(|req, stream: Stream, state, next: &dyn Handler<()>| {
+                let stream = stream.map_response(|mut response| {
+                    response.headers_mut().insert("X-Outer", "1").unwrap();
+                    response
+                });
+                next.handle(req, stream, state)
+            })
+            .nest("/v1/", Router::new().nest("/api", api))
+            .get(Match::new().at(path("v1api")), |_, stream, _| {
+                stream
+                    .respond(Response::build().body("v1api").build())
+                    .unwrap()
+            });
+
+        let response = send(
+            &router,
+            "GET /v1/api/users HTTP/1.1\r\nHost: example.com\r\n\r\n\
+            GET /v1/api HTTP/1.1\r\nHost: example.com\r\n\r\n\
+            GET /v1api HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n",
+        );
+        assert_eq!(
+            response,
+            "HTTP/1.1 200 OK\r\nX-Outer: 1\r\nContent-Length: 13\r\n\r\n/v1/api/users\
+            HTTP/1.1 200 OK\r\nX-Outer: 1\r\nContent-Length: 20\r\n\r\napi index at /v1/api\
+            HTTP/1.1 200 OK\r\nX-Outer: 1\r\nConnection: close\r\nContent-Length: 5\r\n\r\nv1api"
+        );
+
+        let response = send(
+            &router,
+            "GET /v1/api/nothing HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
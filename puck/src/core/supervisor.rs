@@ -0,0 +1,324 @@
+//! Supervises the processes which serve connections, so that a client still receives a response
+//! if the process handling its request panics, and the panic is reported.
+//!
+//! Every connection is served by two processes: a worker, which parses requests and runs the
+//! handlers, and a supervisor which is linked to it. The worker tells the supervisor what it is
+//! doing (and, if it panics, why), and if it dies while a handler is running (but before a
+//! response has been started), the supervisor sends the client a `500 Internal Server Error`
+//! response. Either way, the supervisor logs the crash and sends a [CrashReport] to the reporter
+//! set with [Core::report_crashes](super::Core::report_crashes), if there is one.
+//!
+//! The supervisor is also what closes the connection when the server shuts down (see
+//! [ShutdownHandle](super::ShutdownHandle)).
+
+use std::cell::RefCell;
+
+use lunatic::{
+    host,
+    net::TcpStream,
+    serializer::{Bincode, Serializer},
+    Mailbox, Process,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{response::status::StatusCode, ws::websocket, Response};
+
+use super::{tracker::Tracker, Stream};
+
+/// The type of message the runtime delivers when a linked process dies.
+pub(super) const LINK_TRAPPED: u32 = 1;
+
+/// What the worker serving a connection is doing, or (for the last two variants) what the server
+/// wants the supervisor to do.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum Event {
+    /// The request with the given request line has been parsed and passed to a handler.
+    Handling(String),
+    /// The handler has started sending a response.
+    Responding,
+    /// The handler has upgraded the connection to a WebSocket connection.
+    Upgraded,
+    /// The worker is waiting for the next request.
+    Idle,
+    /// The connection has been closed, so the worker is about to exit.
+    Done,
+    /// The worker has panicked (with the given message), and is about to die.
+    Panicked(String),
+    /// The server is shutting down, so the connection should be closed once the request being
+    /// handled (if any) has been responded to.
+    Shutdown,
+    /// The server has given up waiting for the connection to finish, so it should be closed
+    /// straight away.
+    Abort,
+}
+
+/// A report of a process serving a connection dying (see
+/// [Core::report_crashes](super::Core::report_crashes)).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct CrashReport {
+    /// The request line (e.g. `GET /index.html HTTP/1.1`) of the request which was being handled,
+    /// if there was one.
+    pub request: Option<String>,
+    /// The panic message (including where the panic happened), or `None` if the process died
+    /// without panicking.
+    pub message: Option<String>,
+    /// Whether the client was sent a `500 Internal Server Error` response. This is not possible
+    /// if the handler had already started to respond.
+    pub responded: bool,
+}
+
+/// A handle which the worker uses to keep its supervisor up to date.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Supervisor(Process<Event>);
+
+impl Supervisor {
+    pub(crate) fn notify(&self, event: Event) {
+        self.0.send(event);
+    }
+
+    /// Makes sure that the supervisor hears about it if this process panics.
+    pub(crate) fn report_panics(&self) {
+        SUPERVISOR.with(|supervisor| *supervisor.borrow_mut() = Some(self.clone()));
+
+        let print = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let _ = SUPERVISOR.try_with(|supervisor| {
+                if let Ok(Some(supervisor)) = supervisor.try_borrow().as_deref() {
+                    supervisor.notify(Event::Panicked(info.to_string()));
+                }
+            });
+            print(info);
+        }));
+    }
+}
+
+thread_local! {
+    /// The supervisor of the worker running in this process, for the panic hook to notify.
+    static SUPERVISOR: RefCell<Option<Supervisor>> = const { RefCell::new(None) };
+}
+
+/// The arguments a worker process is started with.
+pub(crate) type WorkerArgs<C> = (TcpStream, C, Supervisor);
+
+/// Runs (inside the supervising process) the worker `entry`, which serves `stream`, and responds
+/// to the client (and reports the crash to `reporter`) if the worker dies.
+///
+/// If there is a `tracker`, it is told once the connection has been closed.
+pub(crate) fn supervise<C>(
+    stream: TcpStream,
+    capture: C,
+    tracker: Option<Tracker>,
+    reporter: Option<Process<CrashReport>>,
+    mailbox: Mailbox<Event>,
+    entry: fn(WorkerArgs<C>, Mailbox<()>),
+) where
+    C: Serialize + DeserializeOwned,
+{
+    // be told about the worker dying, rather than dying along with it
+    unsafe { host::api::process::die_when_link_dies(0) };
+    let worker = Process::spawn_link((stream.clone(), capture, Supervisor(mailbox.this())), entry);
+
+    let outcome = watch(stream, worker);
+
+    if let Some(tracker) = tracker {
+        tracker.closed(mailbox.this());
+    }
+    match outcome {
+        Outcome::Closed => {}
+        Outcome::Crashed(report) => {
+            log::error!(
+                "the process serving a connection died while handling {}: {}",
+                report.request.as_deref().unwrap_or("no request"),
+                report.message.as_deref().unwrap_or("it did not panic"),
+            );
+            if let Some(reporter) = reporter {
+                reporter.send(report);
+            }
+        }
+        Outcome::Stop => terminate(),
+    }
+}
+
+/// How the connection came to an end.
+enum Outcome {
+    /// The worker closed it.
+    Closed,
+    /// The worker died.
+    Crashed(CrashReport),
+    /// The server wants it closed, but the worker is still running (and has to be stopped).
+    Stop,
+}
+
+/// Keeps track of what the worker is doing until the connection is closed.
+///
+/// Nothing is written to the connection while the worker might be writing to it too.
+fn watch(stream: TcpStream, worker: Process<()>) -> Outcome {
+    let mut state = Event::Idle;
+    // the request line is kept until the next request, as it is still worth reporting if the
+    // worker dies after it has started to respond
+    let mut request = None;
+    let mut panic = None;
+    let mut draining = false;
+    loop {
+        // `Mailbox::receive` cannot handle the message sent when a link dies, so this receives
+        // messages directly
+        let message_type = unsafe { host::api::message::receive([].as_ptr(), 0, 0) };
+        if message_type == LINK_TRAPPED {
+            // the client is not told about the connection being kept alive, so it is closed
+            let responded = matches!(state, Event::Handling(_))
+                && Stream::new(stream, false, 1)
+                    .respond(Response::internal_server_error())
+                    .is_ok();
+            return Outcome::Crashed(CrashReport {
+                request,
+                message: panic,
+                responded,
+            });
+        }
+
+        match <Bincode as Serializer<Event>>::decode() {
+            Ok(Event::Done) => return Outcome::Closed,
+            Ok(Event::Panicked(message)) => panic = Some(message),
+            Ok(Event::Shutdown) => {
+                draining = true;
+                match state {
+                    // nothing is lost by closing a connection which is between requests
+                    Event::Idle => return Outcome::Stop,
+                    Event::Upgraded => {
+                        // the worker sends the close frame itself, and the handler finishes once
+                        // the client replies with its own
+                        websocket::notify_going_away(&worker);
+                    }
+                    _ => {}
+                }
+            }
+            Ok(Event::Abort) => {
+                if let Event::Handling(_) = state {
+                    let _ = Stream::new(stream, false, 1)
+                        .respond(Response::error(StatusCode::SERVICE_UNAVAILABLE));
+                }
+                return Outcome::Stop;
+            }
+            Ok(Event::Idle) if draining => return Outcome::Stop,
+            Ok(event) => {
+                match &event {
+                    Event::Handling(line) => request = Some(line.clone()),
+                    Event::Idle => request = None,
+                    _ => {}
+                }
+                state = event;
+            }
+            Err(_) => continue,
+        }
+    }
+}
+
+/// Stops the supervisor, and the worker along with it.
+///
+/// lunatic cannot kill a process, but the worker is linked to the supervisor, so it dies when the
+/// supervisor fails.
+fn terminate() -> ! {
+    // this is not a real panic, so there is no point in reporting it
+    std::panic::set_hook(Box::new(|_| {}));
+    panic!("closing the connection");
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+
+    use lunatic::{
+        net::{TcpListener, TcpStream},
+        Mailbox, Process,
+    };
+
+    use super::{supervise, CrashReport, Event, WorkerArgs};
+    use crate::{
+        core::{connection, ServerConfig},
+        Response,
+    };
+
+    /// Panics while handling a request, either before responding or (if `respond_first` is set)
+    /// after.
+    fn panicking((stream, respond_first, supervisor): WorkerArgs<bool>, _: Mailbox<()>) {
+        connection::serve(
+            stream,
+            &ServerConfig::default(),
+            Some(supervisor),
+            |req, stream| {
+                if respond_first {
+                    stream
+                        .respond(Response::build().body("hello").build())
+                        .unwrap();
+                }
+                panic!("failed to handle {}", req.url())
+            },
+        );
+    }
+
+    fn supervised(
+        (stream, respond_first, reporter): (TcpStream, bool, Process<CrashReport>),
+        mailbox: Mailbox<Event>,
+    ) {
+        supervise(
+            stream,
+            respond_first,
+            None,
+            Some(reporter),
+            mailbox,
+            panicking,
+        );
+    }
+
+    #[lunatic::test]
+    fn test_panic(mailbox: Mailbox<CrashReport>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client
+            .write_all(b"GET /panic HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .unwrap();
+
+        let (server, _) = listener.accept().unwrap();
+        Process::spawn((server, false, mailbox.this()), supervised);
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(response.contains("\r\nConnection: close\r\n"));
+
+        let report = mailbox.receive();
+        assert_eq!(report.request.as_deref(), Some("GET /panic HTTP/1.1"));
+        assert!(report
+            .message
+            .unwrap()
+            .contains("failed to handle http://example.com/panic"));
+        assert!(report.responded);
+    }
+
+    #[lunatic::test]
+    fn test_panic_after_responding(mailbox: Mailbox<CrashReport>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client
+            .write_all(b"GET /panic HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .unwrap();
+
+        let (server, _) = listener.accept().unwrap();
+        Process::spawn((server, true, mailbox.this()), supervised);
+
+        // the response was already sent, so no 500 response follows it
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+
+        let report = mailbox.receive();
+        assert_eq!(report.request.as_deref(), Some("GET /panic HTTP/1.1"));
+        assert!(report
+            .message
+            .unwrap()
+            .contains("failed to handle http://example.com/panic"));
+        assert!(!report.responded);
+    }
+}
@@ -0,0 +1,143 @@
+//! Timeouts for reading from and writing to connections.
+//!
+//! lunatic can time out reads and writes on a `TcpStream`, but the error it returns when one does
+//! refers to an error resource which does not exist, and dropping it traps. The wrappers here
+//! detect timeouts themselves (by how long the read or write took), and report them as errors of
+//! kind [io::ErrorKind::TimedOut] instead.
+
+use std::{
+    io::{self, Read, Write},
+    mem,
+    time::{Duration, Instant},
+};
+
+use lunatic::net::TcpStream;
+
+use crate::request::RequestParseError;
+
+use super::config::MIN_BODY_RATE_GRACE_PERIOD;
+
+/// The error returned when a read or write times out.
+pub(crate) fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, RequestParseError::TimedOut)
+}
+
+/// Rounds `timeout` down to whole milliseconds, which is how lunatic measures timeouts.
+fn whole_millis(timeout: Duration) -> Duration {
+    Duration::from_millis(timeout.as_millis() as u64)
+}
+
+/// Checks whether `error`, returned by a read or write which started at `start` and was allowed
+/// to take `timeout`, was caused by the timeout running out.
+fn check(error: io::Error, start: Instant, timeout: Duration) -> io::Error {
+    if start.elapsed() >= timeout {
+        // see the module documentation for why this can't be dropped
+        mem::forget(error);
+        return timed_out();
+    }
+    error
+}
+
+/// How long reads from a connection may take.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum ReadTimeout {
+    /// Reads fail once this time has passed.
+    Deadline(Instant),
+    /// Each read fails if it takes longer than `timeout`, and (after a grace period) reads fail
+    /// if data has arrived at fewer than `min_rate` bytes per second.
+    Body {
+        timeout: Duration,
+        min_rate: Option<u64>,
+    },
+}
+
+/// The read half of a connection, which fails reads which take too long.
+pub(crate) struct TimedReader {
+    stream: TcpStream,
+    timeout: ReadTimeout,
+    /// The time spent waiting for data since the timeout was last set.
+    waited: Duration,
+    /// The number of bytes received since the timeout was last set.
+    received: u64,
+}
+
+impl TimedReader {
+    pub(crate) fn new(stream: TcpStream, timeout: ReadTimeout) -> Self {
+        Self {
+            stream,
+            timeout,
+            waited: Duration::ZERO,
+            received: 0,
+        }
+    }
+
+    pub(crate) fn set_timeout(&mut self, timeout: ReadTimeout) {
+        self.timeout = timeout;
+        self.waited = Duration::ZERO;
+        self.received = 0;
+    }
+}
+
+impl Read for TimedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout = whole_millis(match self.timeout {
+            ReadTimeout::Deadline(deadline) => deadline.saturating_duration_since(Instant::now()),
+            ReadTimeout::Body { timeout, .. } => timeout,
+        });
+        // lunatic treats a timeout of zero as no timeout at all
+        if timeout.is_zero() {
+            return Err(timed_out());
+        }
+        self.stream.set_read_timeout(Some(timeout));
+
+        let start = Instant::now();
+        let read = self
+            .stream
+            .read(buf)
+            .map_err(|error| check(error, start, timeout))?;
+
+        if let ReadTimeout::Body {
+            min_rate: Some(min_rate),
+            ..
+        } = self.timeout
+        {
+            self.waited += start.elapsed();
+            self.received += read as u64;
+            if self.waited > MIN_BODY_RATE_GRACE_PERIOD
+                && (self.received as f64) < min_rate as f64 * self.waited.as_secs_f64()
+            {
+                return Err(timed_out());
+            }
+        }
+
+        Ok(read)
+    }
+}
+
+/// The write half of a connection, which fails writes which take longer than the timeout.
+pub(crate) struct TimedWriter {
+    stream: TcpStream,
+    timeout: Duration,
+}
+
+impl TimedWriter {
+    pub(crate) fn new(mut stream: TcpStream, timeout: Duration) -> Self {
+        // a timeout of zero would mean no timeout at all
+        let timeout = whole_millis(timeout).max(Duration::from_millis(1));
+        stream.set_write_timeout(Some(timeout));
+        Self { stream, timeout }
+    }
+}
+
+impl Write for TimedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let start = Instant::now();
+        self.stream
+            .write(buf)
+            .map_err(|error| check(error, start, self.timeout))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
@@ -0,0 +1,755 @@
+//! Keeps track of a server's connections, which is how the server limits how many it has open
+//! (see [ServerConfig::max_connections]), reports how many it has open (see [StatsHandle]) and
+//! shuts down (see [ShutdownHandle]).
+//!
+//! Every server has a tracker process, which keeps a list of the connections which are open. The
+//! process which accepts connections asks the tracker whether to serve each one, which is also how
+//! it finds out that it should stop (lunatic has no way to wait for a message and a connection at
+//! the same time). Because of this, the tracker makes a connection to the server when it is asked
+//! to shut down, so that the server is not left waiting for a connection which never comes.
+
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use lunatic::{
+    host,
+    net::TcpStream,
+    serializer::{Bincode, Serializer},
+    Mailbox, Process, Tag,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    config::WhenFull,
+    reject,
+    supervisor::{Event, LINK_TRAPPED},
+    ServerConfig,
+};
+
+/// How long [StatsHandle::stats] waits for the tracker to answer.
+const STATS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The largest number of rejected connections which are sent a `503` response at once (each
+/// from its own process). Connections rejected while this many are still being answered are
+/// closed without a response, so that a flood of connections cannot start any number of
+/// processes.
+const MAX_REJECTING: usize = 64;
+
+/// The type of message the runtime delivers when a receive times out.
+const TIMEOUT: u32 = 9027;
+
+/// A handle which can be used to shut a server down.
+///
+/// Once [ShutdownHandle::shutdown] has been called, the server stops accepting connections and
+/// closes the connections which are idle. Requests which are being handled are allowed to finish
+/// (after which their connections are closed), and clients with open WebSocket connections are
+/// sent a close frame. Once every connection has been closed (or
+/// [ServerConfig::shutdown_timeout](super::ServerConfig::shutdown_timeout) has run out), the
+/// callbacks added with [Core::on_shutdown](super::Core::on_shutdown) are run and
+/// [Core::serve_router](super::Core::serve_router) (or [Core::for_each](super::Core::for_each))
+/// returns.
+///
+/// This can be sent to other processes (e.g. to the handlers, as part of the server's state).
+///
+/// ```ignore
+/// let server = Core::bind("localhost:8080", state, ServerConfig::default())?;
+/// let handle = server.shutdown_handle();
+/// // ...pass `handle` to whatever decides when the server should stop...
+/// server.serve_router(App);
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShutdownHandle(Process<Control>);
+
+impl ShutdownHandle {
+    /// Ask the server to shut down. This returns straight away, rather than waiting for the
+    /// server to finish shutting down.
+    pub fn shutdown(&self) {
+        self.0.send(Control::Shutdown);
+    }
+}
+
+/// A handle which can be used to find out how many connections a server has open (e.g. to report
+/// them to a monitoring system).
+///
+/// Like [ShutdownHandle], this can be sent to other processes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsHandle(Process<Control>);
+
+impl StatsHandle {
+    /// Returns the server's current [ConnectionStats], or `None` if the server has shut down.
+    pub fn stats(&self) -> Option<ConnectionStats> {
+        let tag = Tag::new();
+        // the answer is received by its tag, so this does not interfere with the process's own
+        // mailbox
+        let mailbox: Mailbox<ConnectionStats> = unsafe { Mailbox::new() };
+        self.0.send(Control::Stats(mailbox.this(), tag));
+        mailbox
+            .tag_receive_timeout(Some(&[tag]), STATS_TIMEOUT)
+            .ok()
+    }
+}
+
+/// Counts of a server's connections (see [StatsHandle]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct ConnectionStats {
+    /// The number of connections which are open.
+    pub active: usize,
+    /// The number of connections which have been served since the server started.
+    pub accepted: u64,
+    /// The number of connections which have been rejected (see
+    /// [ServerConfig::max_connections]) since the server started.
+    pub rejected: u64,
+    /// Whether the server has stopped accepting connections until one of the open connections is
+    /// closed (see [WhenFull::Wait]).
+    pub waiting: bool,
+}
+
+/// A message to a server's tracker.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) enum Control {
+    /// The server has accepted a connection from the given address, and wants to know what to do
+    /// with it.
+    Accept(IpAddr),
+    /// The connection from the given address which the tracker said to serve is being served by
+    /// the given supervisor.
+    Opened(Process<Event>, IpAddr),
+    /// The connection served by the given supervisor has been closed.
+    Closed(Process<Event>),
+    /// The connection which the tracker said to reject, to be answered by a process the tracker
+    /// starts.
+    Reject(TcpStream),
+    /// A rejected connection has been sent its response and closed.
+    Rejected,
+    /// The server should shut down.
+    Shutdown,
+    /// Send the current stats to the given process, tagged with the given tag.
+    Stats(Process<ConnectionStats>, Tag),
+}
+
+/// What the server should do with a connection it has accepted.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) enum Admission {
+    /// Serve it.
+    Serve,
+    /// Hand it back to the tracker (see [Tracking::reject]), which tells the client to try again
+    /// later and closes it.
+    Reject,
+    /// Close it without a response (because too many rejected connections are being answered
+    /// already, see [MAX_REJECTING]).
+    Close,
+    /// Close it, and stop accepting connections.
+    Stop,
+}
+
+/// The tracker's answer to the server.
+#[derive(Debug, Serialize, Deserialize)]
+enum Reply {
+    /// What to do with the connection which was just accepted.
+    Admission(Admission),
+    /// Every connection has been closed.
+    Drained,
+}
+
+/// A handle which the supervisor of a connection (or the process answering a rejected
+/// connection) uses to tell the tracker when the connection has been closed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Tracker(Process<Control>);
+
+impl Tracker {
+    pub(crate) fn closed(&self, supervisor: Process<Event>) {
+        self.0.send(Control::Closed(supervisor));
+    }
+
+    pub(crate) fn rejected(&self) {
+        self.0.send(Control::Rejected);
+    }
+}
+
+/// The server's side of the tracker.
+pub(crate) struct Tracking {
+    tracker: Process<Control>,
+    /// The tracker's replies are tagged, so that they do not get mixed up with any other messages
+    /// the server's process receives.
+    tag: Tag,
+}
+
+impl fmt::Debug for Tracking {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tracking")
+            .field("tracker", &self.tracker)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Tracking {
+    /// Starts the tracker of a server which is bound to `addr`.
+    pub(crate) fn start(addr: SocketAddr, config: ServerConfig) -> Self {
+        let tag = Tag::new();
+        let tracker = Process::spawn_link((replies().this(), tag, addr, config), track);
+        Self { tracker, tag }
+    }
+
+    pub(crate) fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle(self.tracker.clone())
+    }
+
+    pub(crate) fn stats_handle(&self) -> StatsHandle {
+        StatsHandle(self.tracker.clone())
+    }
+
+    pub(crate) fn tracker(&self) -> Tracker {
+        Tracker(self.tracker.clone())
+    }
+
+    /// Asks the tracker what to do with a connection from `ip` which the server has accepted.
+    /// This blocks for as long as the server has to wait for a connection to be closed (see
+    /// [WhenFull::Wait]).
+    pub(crate) fn admit(&self, ip: IpAddr) -> Admission {
+        self.tracker.send(Control::Accept(ip));
+        loop {
+            if let Reply::Admission(admission) = replies().tag_receive(Some(&[self.tag])) {
+                return admission;
+            }
+        }
+    }
+
+    /// Hands the tracker the connection which it said to reject.
+    ///
+    /// The tracker answers it from a process linked to the tracker, so that it finds out about
+    /// the connection being closed even if that process dies.
+    pub(crate) fn reject(&self, stream: TcpStream) {
+        self.tracker.send(Control::Reject(stream));
+    }
+
+    /// Tells the tracker that the connection from `ip` which it said to serve is being served by
+    /// `supervisor`.
+    pub(crate) fn opened(&self, supervisor: Process<Event>, ip: IpAddr) {
+        self.tracker.send(Control::Opened(supervisor, ip));
+    }
+
+    /// Waits until every connection has been closed (or the shutdown timeout has run out). This
+    /// must only be called once [Tracking::admit] has returned [Admission::Stop].
+    pub(crate) fn wait(&self) {
+        while !matches!(replies().tag_receive(Some(&[self.tag])), Reply::Drained) {}
+    }
+}
+
+/// The mailbox the server's process receives the tracker's replies through.
+fn replies() -> Mailbox<Reply> {
+    // the replies are only ever received by their tag, so this does not interfere with the
+    // process's own mailbox
+    unsafe { Mailbox::new() }
+}
+
+/// What the tracker receives.
+enum Received {
+    Control(Control),
+    /// A process answering a rejected connection died, rather than telling the tracker that it
+    /// was done.
+    RejectDied,
+    /// The server's process died.
+    ServerDied,
+    /// Nothing arrived before the timeout ran out.
+    TimedOut,
+    /// A message which could not be decoded.
+    Invalid,
+}
+
+/// Receives the next message, waiting for at most `timeout` (or for as long as it takes, if it
+/// is `None`). Processes answering rejected connections are linked with `reject_tag`.
+fn receive(reject_tag: Tag, timeout: Option<Duration>) -> Received {
+    let timeout = match timeout {
+        // zero would mean waiting forever
+        Some(timeout) => u32::try_from(timeout.as_millis())
+            .unwrap_or(u32::MAX)
+            .max(1),
+        None => 0,
+    };
+    // `Mailbox::receive` cannot handle the message sent when a link dies, so this receives
+    // messages directly
+    match unsafe { host::api::message::receive([].as_ptr(), 0, timeout) } {
+        LINK_TRAPPED if unsafe { host::api::message::get_tag() } == reject_tag.id() => {
+            Received::RejectDied
+        }
+        LINK_TRAPPED => Received::ServerDied,
+        TIMEOUT => Received::TimedOut,
+        _ => match <Bincode as Serializer<Control>>::decode() {
+            Ok(message) => Received::Control(message),
+            Err(_) => Received::Invalid,
+        },
+    }
+}
+
+/// The connections which are open.
+#[derive(Default)]
+struct Connections {
+    open: Vec<(Process<Event>, IpAddr)>,
+    /// The number of open connections from each address.
+    per_ip: HashMap<IpAddr, usize>,
+    /// Connections which were closed before the tracker heard about them being opened.
+    closed: Vec<Process<Event>>,
+}
+
+impl Connections {
+    fn len(&self) -> usize {
+        self.open.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.open.is_empty()
+    }
+
+    fn count_from(&self, ip: IpAddr) -> usize {
+        self.per_ip.get(&ip).copied().unwrap_or(0)
+    }
+
+    /// Returns whether the connection is still open.
+    fn opened(&mut self, supervisor: &Process<Event>, ip: IpAddr) -> bool {
+        match self.closed.iter().position(|closed| closed == supervisor) {
+            Some(index) => {
+                self.closed.swap_remove(index);
+                false
+            }
+            None => {
+                self.open.push((supervisor.clone(), ip));
+                *self.per_ip.entry(ip).or_insert(0) += 1;
+                true
+            }
+        }
+    }
+
+    fn closed(&mut self, supervisor: Process<Event>) {
+        match self.open.iter().position(|(open, _)| *open == supervisor) {
+            Some(index) => {
+                let (_, ip) = self.open.swap_remove(index);
+                if let Some(count) = self.per_ip.get_mut(&ip) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.per_ip.remove(&ip);
+                    }
+                }
+            }
+            None => self.closed.push(supervisor),
+        }
+    }
+
+    fn supervisors(&self) -> impl Iterator<Item = &Process<Event>> {
+        self.open.iter().map(|(supervisor, _)| supervisor)
+    }
+}
+
+/// The state of a server's tracker.
+struct State {
+    server: Process<Reply>,
+    tag: Tag,
+    config: ServerConfig,
+    connections: Connections,
+    /// The address of the connection the server is waiting to be allowed to serve.
+    waiting: Option<IpAddr>,
+    /// The number of rejected connections which are being answered.
+    rejecting: usize,
+    accepted: u64,
+    rejected: u64,
+}
+
+impl State {
+    fn reply(&self, admission: Admission) {
+        self.server.tag_send(self.tag, Reply::Admission(admission));
+    }
+
+    /// Decides what to do with a connection from `ip`, which is either answered straight away or
+    /// (if the server is full) once a connection has been closed.
+    fn admit(&mut self, ip: IpAddr) {
+        let ip_full = self
+            .config
+            .max_connections_per_ip
+            .map_or(false, |max| self.connections.count_from(ip) >= max);
+        let full = self
+            .config
+            .max_connections
+            .map_or(false, |max| self.connections.len() >= max);
+
+        if ip_full || (full && self.config.when_full == WhenFull::Reject) {
+            self.rejected += 1;
+            if self.rejecting < MAX_REJECTING {
+                self.rejecting += 1;
+                self.reply(Admission::Reject);
+            } else {
+                self.reply(Admission::Close);
+            }
+        } else if full {
+            self.waiting = Some(ip);
+        } else {
+            self.accepted += 1;
+            self.reply(Admission::Serve);
+        }
+    }
+
+    fn stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            active: self.connections.len(),
+            accepted: self.accepted,
+            rejected: self.rejected,
+            waiting: self.waiting.is_some(),
+        }
+    }
+}
+
+fn track(
+    (server, tag, addr, config): (Process<Reply>, Tag, SocketAddr, ServerConfig),
+    mailbox: Mailbox<Control>,
+) {
+    // be told about a process answering a rejected connection dying, rather than dying along
+    // with it
+    unsafe { host::api::process::die_when_link_dies(0) };
+    let reject_tag = Tag::new();
+
+    let mut state = State {
+        server,
+        tag,
+        config,
+        connections: Connections::default(),
+        waiting: None,
+        rejecting: 0,
+        accepted: 0,
+        rejected: 0,
+    };
+    // when the server was asked to shut down
+    let mut deadline: Option<Instant> = None;
+    // whether the server has been told to stop accepting connections
+    let mut stopped = false;
+
+    loop {
+        let received = match deadline {
+            Some(deadline) if stopped => {
+                if state.connections.is_empty() {
+                    break;
+                }
+                receive(
+                    reject_tag,
+                    Some(deadline.saturating_duration_since(Instant::now())),
+                )
+            }
+            // the server has to be told to stop, however long that takes
+            _ => receive(reject_tag, None),
+        };
+        let message = match received {
+            Received::Control(message) => message,
+            Received::RejectDied => {
+                state.rejecting -= 1;
+                continue;
+            }
+            // there is nobody left to report to
+            Received::ServerDied => return,
+            Received::TimedOut => break,
+            Received::Invalid => continue,
+        };
+
+        match message {
+            Control::Accept(_) if deadline.is_some() => {
+                state.reply(Admission::Stop);
+                stopped = true;
+            }
+            Control::Accept(ip) => state.admit(ip),
+            Control::Opened(supervisor, ip) => {
+                if state.connections.opened(&supervisor, ip) && deadline.is_some() {
+                    supervisor.send(Event::Shutdown);
+                }
+            }
+            Control::Closed(supervisor) => {
+                state.connections.closed(supervisor);
+                if let Some(ip) = state.waiting.take() {
+                    state.admit(ip);
+                }
+            }
+            Control::Reject(stream) => {
+                Process::spawn_link_tag(
+                    (stream, state.config, Tracker(mailbox.this())),
+                    reject_tag,
+                    reject,
+                );
+            }
+            Control::Rejected => state.rejecting -= 1,
+            Control::Shutdown if deadline.is_none() => {
+                deadline = Some(Instant::now() + config.shutdown_timeout);
+                for supervisor in state.connections.supervisors() {
+                    supervisor.send(Event::Shutdown);
+                }
+                if state.waiting.take().is_some() {
+                    state.reply(Admission::Stop);
+                    stopped = true;
+                } else {
+                    wake(addr);
+                }
+            }
+            Control::Shutdown => {}
+            Control::Stats(process, tag) => process.tag_send(tag, state.stats()),
+        }
+    }
+
+    for supervisor in state.connections.supervisors() {
+        supervisor.send(Event::Abort);
+    }
+    state.server.tag_send(tag, Reply::Drained);
+}
+
+/// Connects to the server, so that it stops waiting for a connection and checks in with the
+/// tracker.
+fn wake(addr: SocketAddr) {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    if let Err(error) = TcpStream::connect(SocketAddr::new(ip, addr.port())) {
+        log::error!("failed to wake the server up to shut it down: {}", error);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        cell::Cell,
+        io::{Read, Write},
+        net::SocketAddr,
+        rc::Rc,
+        time::Duration,
+    };
+
+    use lunatic::{net::TcpStream, Mailbox, Process};
+    use serde::{Deserialize, Serialize};
+
+    use super::{ConnectionStats, ShutdownHandle, StatsHandle};
+    use crate::{
+        core::{
+            router::{
+                match_url::{path, Match},
+                MakeRouter, Router,
+            },
+            Core, ServerConfig, UsedStream, WhenFull,
+        },
+        Response,
+    };
+
+    #[derive(Clone, Serialize, Deserialize)]
+    struct Slow;
+
+    impl MakeRouter<()> for Slow {
+        fn make_router(&self) -> Router<()> {
+            Router::new().get(Match::new().at(path("slow")), |_, stream, _| {
+                lunatic::sleep(Duration::from_millis(300));
+                stream
+                    .respond(Response::build().body("done").build())
+                    .unwrap()
+            })
+        }
+    }
+
+    /// Makes a request which is still being handled when the server is told to shut down, and
+    /// sends back the responses to it and to an idle connection.
+    fn client(
+        (addr, handle, test): (SocketAddr, ShutdownHandle, Process<(String, String)>),
+        _: Mailbox<()>,
+    ) {
+        let mut idle = TcpStream::connect(addr).unwrap();
+        let mut busy = TcpStream::connect(addr).unwrap();
+        busy.write_all(b"GET /slow HTTP/1.1\r\nHost: a\r\n\r\n")
+            .unwrap();
+        lunatic::sleep(Duration::from_millis(100));
+        handle.shutdown();
+
+        let mut busy_response = String::new();
+        busy.read_to_string(&mut busy_response).unwrap();
+        let mut idle_response = String::new();
+        idle.read_to_string(&mut idle_response).unwrap();
+        test.send((busy_response, idle_response));
+    }
+
+    #[lunatic::test]
+    fn test_shutdown(mailbox: Mailbox<(String, String)>) {
+        let shut_down = Rc::new(Cell::new(false));
+        let server = Core::bind("127.0.0.1:0", (), ServerConfig::default())
+            .unwrap()
+            .on_shutdown({
+                let shut_down = shut_down.clone();
+                move || shut_down.set(true)
+            });
+
+        Process::spawn(
+            (
+                server.local_addr().unwrap(),
+                server.shutdown_handle(),
+                mailbox.this(),
+            ),
+            client,
+        );
+        server.serve_router(Slow);
+        assert!(shut_down.get());
+
+        let (busy, idle) = mailbox.receive();
+        assert!(busy.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(busy.ends_with("\r\n\r\ndone"));
+        assert_eq!(idle, "");
+    }
+
+    #[derive(Clone, Serialize, Deserialize)]
+    struct Echo;
+
+    impl MakeRouter<()> for Echo {
+        fn make_router(&self) -> Router<()> {
+            Router::new().get(Match::new().at(path("ws")), |req, stream, _| {
+                let mut ws = match stream.upgrade(&req) {
+                    Ok(ws) => ws,
+                    Err(used) => return used,
+                };
+                while let Some(Ok(_)) = ws.next() {}
+                ws.close().unwrap_or_else(|_| UsedStream::empty())
+            })
+        }
+    }
+
+    /// Opens a WebSocket, then tells the server to shut down. Sends back everything the server
+    /// sent over the WebSocket, once the client has answered its close frame.
+    fn websocket_client(
+        (addr, handle, test): (SocketAddr, ShutdownHandle, Process<Vec<u8>>),
+        _: Mailbox<()>,
+    ) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(
+                b"GET /ws HTTP/1.1\r\nHost: a\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            )
+            .unwrap();
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            stream.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        handle.shutdown();
+
+        let mut sent = vec![0; 4];
+        stream.read_exact(&mut sent).unwrap();
+        stream.write_all(&[0x88, 0x80, 0, 0, 0, 0]).unwrap();
+        stream.read_to_end(&mut sent).unwrap();
+        test.send(sent);
+    }
+
+    #[lunatic::test]
+    fn test_shutdown_websocket(mailbox: Mailbox<Vec<u8>>) {
+        let server = Core::bind("127.0.0.1:0", (), ServerConfig::default()).unwrap();
+        Process::spawn(
+            (
+                server.local_addr().unwrap(),
+                server.shutdown_handle(),
+                mailbox.this(),
+            ),
+            websocket_client,
+        );
+        server.serve_router(Echo);
+
+        // a single close frame, with the status code 1001 ("going away")
+        assert_eq!(mailbox.receive(), [0x88, 0x02, 0x03, 0xE9]);
+    }
+
+    type LimitArgs = (
+        SocketAddr,
+        StatsHandle,
+        ShutdownHandle,
+        Process<(String, ConnectionStats)>,
+    );
+
+    /// Asks the server for its stats until `ready` returns true for them, and returns them.
+    fn wait_for(stats: &StatsHandle, ready: impl Fn(&ConnectionStats) -> bool) -> ConnectionStats {
+        loop {
+            let current = stats.stats().unwrap();
+            if ready(&current) {
+                return current;
+            }
+            lunatic::sleep(Duration::from_millis(5));
+        }
+    }
+
+    /// Opens a connection, then sends a request over a second one once the first has been
+    /// accepted, closing the first once the server has decided what to do with the second. Sends
+    /// back the response to the request, and the stats from while the first connection was still
+    /// open.
+    fn second_client((addr, stats, shutdown, test): LimitArgs, _: Mailbox<()>) {
+        let first = TcpStream::connect(addr).unwrap();
+        wait_for(&stats, |stats| stats.active == 1);
+        let mut second = TcpStream::connect(addr).unwrap();
+        second
+            .write_all(b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let stats = wait_for(&stats, |stats| stats.rejected == 1 || stats.waiting);
+        drop(first);
+
+        let mut response = String::new();
+        second.read_to_string(&mut response).unwrap();
+        shutdown.shutdown();
+        test.send((response, stats));
+    }
+
+    fn serve_two_clients(
+        config: ServerConfig,
+        mailbox: Mailbox<(String, ConnectionStats)>,
+    ) -> (String, ConnectionStats) {
+        let server = Core::bind("127.0.0.1:0", (), config).unwrap();
+        Process::spawn(
+            (
+                server.local_addr().unwrap(),
+                server.stats_handle(),
+                server.shutdown_handle(),
+                mailbox.this(),
+            ),
+            second_client,
+        );
+        server.serve_router(Slow);
+        mailbox.receive()
+    }
+
+    #[lunatic::test]
+    fn test_reject_when_full(mailbox: Mailbox<(String, ConnectionStats)>) {
+        let config = ServerConfig::default()
+            .max_connections(Some(1))
+            .when_full(WhenFull::Reject)
+            .retry_after(Duration::from_millis(1500));
+
+        let (response, stats) = serve_two_clients(config, mailbox);
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(response.contains("\r\nRetry-After: 2\r\n"));
+        assert_eq!(
+            (stats.active, stats.accepted, stats.rejected, stats.waiting),
+            (1, 1, 1, false)
+        );
+    }
+
+    #[lunatic::test]
+    fn test_wait_when_full(mailbox: Mailbox<(String, ConnectionStats)>) {
+        let config = ServerConfig::default().max_connections(Some(1));
+
+        let (response, stats) = serve_two_clients(config, mailbox);
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert_eq!(
+            (stats.active, stats.accepted, stats.rejected, stats.waiting),
+            (1, 1, 0, true)
+        );
+    }
+
+    #[lunatic::test]
+    fn test_max_connections_per_ip(mailbox: Mailbox<(String, ConnectionStats)>) {
+        let config = ServerConfig::default().max_connections_per_ip(Some(1));
+
+        let (response, stats) = serve_two_clients(config, mailbox);
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(response.contains("\r\nRetry-After: 1\r\n"));
+        assert_eq!((stats.active, stats.rejected), (1, 1));
+    }
+}
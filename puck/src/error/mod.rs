@@ -0,0 +1,125 @@
+//! Errors which handlers can return, and how they are turned into responses.
+//!
+//! Handlers can return `Result<UsedStream, PuckError>` (rather than a [UsedStream]), so that `?`
+//! can be used inside them. If a handler returns an error, the client is sent the error page for
+//! the error's status (see [Router::error_page]).
+//!
+//! ```ignore
+//! router.post(Match::new().at(path("items")), |mut req, stream, _| {
+//!     let item: Item = req.take_body().json()?;
+//!     Ok(stream.respond(Response::json(&save(item)?)?)?)
+//! })
+//! ```
+//!
+//! [UsedStream]: crate::core::UsedStream
+//! [Router::error_page]: crate::core::router::Router::error_page
+
+use std::io;
+
+use crate::{
+    body::{form::FormError, json::JsonError, multipart::MultipartError},
+    request::RequestParseError,
+    response::status::StatusCode,
+    Response,
+};
+
+/// Something which can be sent as a response.
+///
+/// This is how an error returned by a handler is turned into the response the client is sent
+/// (unless an error page is registered for its status, see
+/// [Router::error_page](crate::core::router::Router::error_page)). It can also be used to
+/// respond with an error directly, e.g. `stream.respond(error.into_response())`.
+pub trait IntoResponse {
+    /// Convert this into a response.
+    fn into_response(self) -> Response;
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> Response {
+        self
+    }
+}
+
+impl IntoResponse for StatusCode {
+    /// The default error page for the status (see [Response::error]).
+    fn into_response(self) -> Response {
+        Response::error(self)
+    }
+}
+
+/// An error which occurred while handling a request.
+#[derive(thiserror::Error, Debug)]
+pub enum PuckError {
+    /// The client should be sent the error page for this status.
+    #[error("{0}")]
+    Status(StatusCode),
+    /// The client should be sent this response.
+    #[error("responding with {}", .0.status())]
+    Response(Box<Response>),
+    /// An IO error (e.g. the connection failed while the response was being sent). The client is
+    /// sent a `500 Internal Server Error` response if possible.
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    /// Any other error. The client is sent a `500 Internal Server Error` response.
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl PuckError {
+    /// The status of the response which the client is sent because of this error.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            PuckError::Status(status) => *status,
+            PuckError::Response(response) => response.status(),
+            PuckError::Io(_) | PuckError::Other(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl IntoResponse for PuckError {
+    fn into_response(self) -> Response {
+        match self {
+            PuckError::Response(response) => *response,
+            error => Response::error(error.status()),
+        }
+    }
+}
+
+impl From<StatusCode> for PuckError {
+    fn from(status: StatusCode) -> Self {
+        PuckError::Status(status)
+    }
+}
+
+impl From<Response> for PuckError {
+    fn from(response: Response) -> Self {
+        PuckError::Response(Box::new(response))
+    }
+}
+
+impl From<serde_json::Error> for PuckError {
+    /// Errors serializing a response (see [Response::json]).
+    fn from(error: serde_json::Error) -> Self {
+        PuckError::Other(error.into())
+    }
+}
+
+macro_rules! status_errors {
+    ($($error:ty),*) => {
+        $(
+            impl From<$error> for PuckError {
+                fn from(error: $error) -> Self {
+                    PuckError::Status(error.status())
+                }
+            }
+
+            impl IntoResponse for $error {
+                fn into_response(self) -> Response {
+                    Response::error(self.status())
+                }
+            }
+        )*
+    };
+}
+
+status_errors!(JsonError, FormError, MultipartError, RequestParseError);
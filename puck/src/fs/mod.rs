@@ -0,0 +1,432 @@
+//! Serving files from disk.
+
+use std::{
+    convert::TryFrom,
+    fs::{self, File, Metadata},
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+
+use crate::{
+    body::{
+        mime::{Mime, HTML},
+        Body,
+    },
+    core::{router::Handler, Stream, UsedStream},
+    request::Method,
+    response::{
+        range::{self, Ranges},
+        status::StatusCode,
+    },
+    Request, Response,
+};
+
+/// The characters which are percent-encoded in the links of a directory listing.
+const LINK: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// A [Handler] which serves the files in a directory.
+///
+/// The path of the request's URL is treated as a path within the directory, so this is usually
+/// mounted at a prefix using [Router::mount](crate::core::router::Router::mount):
+///
+/// ```ignore
+/// Router::new().mount("/static", ServeDir::new("static"))
+/// ```
+///
+/// - Paths containing `..` segments are rejected, so files outside the directory are never
+///   served.
+/// - The `Content-Type` is guessed from the file's extension (see [Mime::guess_from_path]).
+/// - Requests for a directory are answered with its `index.html` file (see [ServeDir::index]),
+///   or a listing of its contents (if enabled with [ServeDir::listing]).
+/// - Each response has `Last-Modified` and `ETag` headers, and `304 Not Modified` is sent in
+///   response to requests with a matching `If-None-Match` or `If-Modified-Since` header.
+///
+/// Only `GET` and `HEAD` requests are accepted.
+#[derive(Debug, Clone)]
+pub struct ServeDir {
+    root: PathBuf,
+    index: Option<String>,
+    listing: bool,
+}
+
+impl ServeDir {
+    /// Serve the files in the directory at `root`.
+    pub fn new(root: impl Into<PathBuf>) -> ServeDir {
+        ServeDir {
+            root: root.into(),
+            index: Some("index.html".to_string()),
+            listing: false,
+        }
+    }
+
+    /// Set the name of the file which is served in response to a request for a directory
+    /// (`index.html` by default), or `None` to never serve one.
+    pub fn index(mut self, index: Option<impl Into<String>>) -> ServeDir {
+        self.index = index.map(Into::into);
+        self
+    }
+
+    /// Set whether a listing of a directory's contents is sent in response to requests for a
+    /// directory without an index file. This is disabled by default.
+    pub fn listing(mut self, listing: bool) -> ServeDir {
+        self.listing = listing;
+        self
+    }
+
+    /// Works out the response to a request.
+    pub(crate) fn response(&self, req: &Request) -> Response {
+        if req.method() != &Method::Get && req.method() != &Method::Head {
+            let mut response = Response::error(StatusCode::METHOD_NOT_ALLOWED);
+            response
+                .headers
+                .insert_unchecked("Allow".to_string(), "GET, HEAD".to_string());
+            return response;
+        }
+
+        let path = match resolve(&self.root, req.url().path()) {
+            Some(path) => path,
+            None => return Response::not_found(),
+        };
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(e) => return io_error_response(e),
+        };
+
+        if !metadata.is_dir() {
+            return file_response(req, &path, &metadata);
+        }
+
+        // relative links from the index file (or listing) only work if the URL ends in a slash
+        if !req.url().path().ends_with('/') {
+            return Response::build()
+                .status_code(StatusCode::MOVED_PERMANENTLY)
+                .header("Location", directory_location(req))
+                .build();
+        }
+
+        if let Some(index) = &self.index {
+            let index = path.join(index);
+            if let Ok(metadata) = fs::metadata(&index) {
+                if metadata.is_file() {
+                    return file_response(req, &index, &metadata);
+                }
+            }
+        }
+
+        if self.listing {
+            return listing_response(req, &path).unwrap_or_else(io_error_response);
+        }
+
+        Response::not_found()
+    }
+}
+
+impl<STATE> Handler<STATE> for ServeDir {
+    fn handle(&self, req: Request, stream: Stream, _: STATE) -> UsedStream {
+        stream
+            .respond(self.response(&req))
+            .unwrap_or_else(|_| UsedStream::empty())
+    }
+}
+
+/// The URL to redirect a request for a directory to, which is the one requested with a slash on
+/// the end.
+fn directory_location(req: &Request) -> String {
+    // `//evil.example/` would be a redirect to another host, so the path is given a single
+    // leading slash
+    let path = format!("{}{}", req.base_path(), req.url().path());
+    let mut location = format!("/{}/", path.trim_start_matches('/'));
+    if let Some(query) = req.url().query() {
+        location.push('?');
+        location.push_str(query);
+    }
+    location
+}
+
+/// Works out the path of the file which `url_path` refers to within `root`.
+///
+/// Returns `None` if the path contains a `..` segment (or anything else which could refer to a
+/// file outside `root`), or if it is not valid percent-encoded UTF-8.
+fn resolve(root: &Path, url_path: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for segment in url_path.split('/') {
+        let segment = percent_decode_str(segment).decode_utf8().ok()?;
+        match segment.as_ref() {
+            "" | "." => {}
+            ".." => return None,
+            segment if segment.contains(&['/', '\\', '\0'][..]) => return None,
+            segment => path.push(segment),
+        }
+    }
+    Some(path)
+}
+
+/// Responds with the file at `path`, or `304 Not Modified` if the client already has it.
+fn file_response(req: &Request, path: &Path, metadata: &Metadata) -> Response {
+    let validators = Validators::new(metadata);
+
+    if validators.not_modified(req) {
+        return validators.apply(
+            Response::build()
+                .status_code(StatusCode::NOT_MODIFIED)
+                .build(),
+        );
+    }
+
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => return io_error_response(e),
+    };
+    let mime = Mime::guess_from_path(path);
+    let response = validators.apply(
+        Response::build()
+            .header("Content-Type", &mime)
+            .header("Accept-Ranges", "bytes")
+            .build(),
+    );
+
+    // files can be seeked, so skipping to a range does not need to read what comes before it
+    let length = metadata.len();
+    match range::requested_ranges(req, &response.headers, length) {
+        Ranges::Full => {
+            // a file too long for its length to fit in a `usize` (which is only 32 bits on
+            // wasm32) is sent as if its length were unknown
+            let mut response = response;
+            response.replace_body(
+                Body::from_reader(BufReader::new(file), usize::try_from(length).ok())
+                    .with_mime(mime),
+            );
+            response
+        }
+        Ranges::Partial(ranges) => {
+            range::partial(response, BufReader::new(file), &mime, ranges, length)
+        }
+        Ranges::Unsatisfiable => range::unsatisfiable(length),
+    }
+}
+
+/// The `ETag` and `Last-Modified` values of a file, which clients use to check whether their copy
+/// of it is up to date.
+pub(crate) struct Validators {
+    pub(crate) etag: Option<String>,
+    pub(crate) last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    pub(crate) fn new(metadata: &Metadata) -> Validators {
+        let modified = metadata.modified().ok();
+        let since_epoch = modified.and_then(|modified| modified.duration_since(UNIX_EPOCH).ok());
+        Validators {
+            etag: since_epoch.map(|since_epoch| {
+                format!(
+                    "\"{:x}-{:x}.{:x}\"",
+                    metadata.len(),
+                    since_epoch.as_secs(),
+                    since_epoch.subsec_nanos()
+                )
+            }),
+            last_modified: modified,
+        }
+    }
+
+    /// Whether the request's conditional headers show that the client's copy is up to date.
+    ///
+    /// `If-None-Match` takes precedence over `If-Modified-Since`, as described in
+    /// [RFC 9110](https://www.rfc-editor.org/rfc/rfc9110#section-13.2.2).
+    pub(crate) fn not_modified(&self, req: &Request) -> bool {
+        if req.headers().contains_key("if-none-match") {
+            let etag = match &self.etag {
+                Some(etag) => etag,
+                None => return false,
+            };
+            return req.headers().get_list("if-none-match").any(|candidate| {
+                candidate == "*" || candidate.trim_start_matches("W/") == etag.as_str()
+            });
+        }
+
+        match (req.headers().get("if-modified-since"), self.last_modified) {
+            (Some(since), Some(modified)) => httpdate::parse_http_date(since)
+                .map(|since| truncate_to_seconds(modified) <= since)
+                .unwrap_or(false),
+            _ => false,
+        }
+    }
+
+    /// Adds the `ETag` and `Last-Modified` headers to the response.
+    pub(crate) fn apply(&self, mut response: Response) -> Response {
+        if let Some(etag) = &self.etag {
+            response
+                .headers
+                .insert_unchecked("ETag".to_string(), etag.clone());
+        }
+        if let Some(modified) = self.last_modified {
+            response.headers.insert_unchecked(
+                "Last-Modified".to_string(),
+                httpdate::fmt_http_date(modified),
+            );
+        }
+        response
+    }
+}
+
+/// HTTP dates only have a precision of one second.
+fn truncate_to_seconds(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since_epoch) => UNIX_EPOCH + std::time::Duration::from_secs(since_epoch.as_secs()),
+        Err(_) => time,
+    }
+}
+
+/// Responds with an HTML page listing the contents of the directory at `path`.
+fn listing_response(req: &Request, path: &Path) -> io::Result<Response> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let mut name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_dir() {
+            name.push('/');
+        }
+        entries.push(name);
+    }
+    entries.sort();
+
+    let title = escape_html(&format!("{}{}", req.base_path(), req.url().path()));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><title>Index of {0}</title></head>\n<body>\n\
+        <h1>Index of {0}</h1>\n<ul>\n",
+        title
+    );
+    if req.url().path() != "/" {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for name in entries {
+        html.push_str(&format!(
+            "<li><a href=\"./{}\">{}</a></li>\n",
+            utf8_percent_encode(&name, LINK),
+            escape_html(&name)
+        ));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+
+    Ok(Response::build()
+        .header("Content-Type", HTML)
+        .body(html)
+        .build())
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Responds to an error encountered while reading a file or directory.
+fn io_error_response(error: io::Error) -> Response {
+    match error.kind() {
+        io::ErrorKind::NotFound => Response::not_found(),
+        io::ErrorKind::PermissionDenied => Response::error(StatusCode::FORBIDDEN),
+        _ => Response::internal_server_error(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        path::{Path, PathBuf},
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use crate::{request::Method, Request};
+
+    use super::{directory_location, escape_html, resolve, Validators};
+
+    #[lunatic::test]
+    fn test_resolve() {
+        let root = Path::new("static");
+        assert_eq!(
+            resolve(root, "/css/site%20main.css"),
+            Some(PathBuf::from("static/css/site main.css"))
+        );
+        assert_eq!(resolve(root, "/./a//b/"), Some(PathBuf::from("static/a/b")));
+        assert_eq!(resolve(root, "/"), Some(PathBuf::from("static")));
+        assert_eq!(resolve(root, "/../secret"), None);
+        assert_eq!(resolve(root, "/a/%2E%2E/%2e%2e/secret"), None);
+        assert_eq!(resolve(root, "/a%2F..%2F..%2Fsecret"), None);
+        assert_eq!(resolve(root, "/%FF"), None);
+    }
+
+    #[lunatic::test]
+    fn test_directory_location() {
+        let location =
+            |url: &str| directory_location(&Request::build(url).method(Method::Get).build());
+
+        assert_eq!(location("http://example.com/dir?a=b"), "/dir/?a=b");
+        // this would otherwise be a redirect to `http://dir/`
+        assert_eq!(location("http://example.com//dir"), "/dir/");
+        assert_eq!(
+            location("http://example.com///evil.example"),
+            "/evil.example/"
+        );
+    }
+
+    #[lunatic::test]
+    fn test_not_modified() {
+        let validators = Validators {
+            etag: Some("\"1a-5f5e100.0\"".to_string()),
+            last_modified: Some(UNIX_EPOCH + Duration::from_millis(1_000_000_000_500)),
+        };
+        let request = |headers: &[(&str, &str)]| {
+            Request::build("http://example.com/")
+                .method(Method::Get)
+                .headers(
+                    headers
+                        .iter()
+                        .map(|(key, value)| (key.to_string(), value.to_string())),
+                )
+                .build()
+        };
+
+        assert!(!validators.not_modified(&request(&[])));
+        assert!(validators.not_modified(&request(&[(
+            "If-None-Match",
+            "\"other\", W/\"1a-5f5e100.0\""
+        )])));
+        assert!(validators.not_modified(&request(&[("If-None-Match", "*")])));
+        // `If-None-Match` takes precedence
+        assert!(!validators.not_modified(&request(&[
+            ("If-None-Match", "\"other\""),
+            ("If-Modified-Since", "Sun, 09 Sep 2001 01:46:40 GMT")
+        ])));
+        assert!(validators.not_modified(&request(&[(
+            "If-Modified-Since",
+            "Sun, 09 Sep 2001 01:46:40 GMT"
+        )])));
+        assert!(!validators.not_modified(&request(&[(
+            "If-Modified-Since",
+            "Sun, 09 Sep 2001 01:46:39 GMT"
+        )])));
+    }
+
+    #[lunatic::test]
+    fn test_escape_html() {
+        assert_eq!(
+            escape_html("<a href=\"x\">'&'</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;"
+        );
+    }
+}
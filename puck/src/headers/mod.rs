@@ -0,0 +1,248 @@
+//! HTTP headers.
+
+use std::{fmt, iter::FromIterator};
+
+use crate::request::is_token_char;
+
+/// The headers of a request or response.
+///
+/// Header names are case-insensitive (so `headers.get("content-type")` finds a `Content-Type`
+/// header), and a header may have several values (e.g. one for each `Set-Cookie` line of a
+/// response). Headers are kept in the order in which they were added, and the case of their names
+/// is preserved when they are written out.
+///
+/// Names must be valid [tokens](https://www.rfc-editor.org/rfc/rfc9110#section-5.6.2), and
+/// values must not contain line breaks (which would otherwise allow whoever supplied the value to
+/// add headers of their own, or a body, to the message).
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    /// Construct a new, empty `HeaderMap`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the first value of the header with the given name.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns every value of the header with the given name, in the order they were added.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns every value of the header with the given name, split on commas (for headers such as
+    /// `Connection` or `Accept`, whose values are comma-separated lists which may be split across
+    /// several lines).
+    ///
+    /// Surrounding whitespace and empty elements are removed.
+    pub fn get_list<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|element| !element.is_empty())
+    }
+
+    /// Whether or not a header with the given name is present.
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Sets the header with the given name to `value`, replacing any existing values (and
+    /// returning the first of them).
+    pub fn insert(
+        &mut self,
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<Option<String>, InvalidHeader> {
+        let (name, value) = validate(name.into(), value.into())?;
+        let existing = self.remove(&name);
+        self.entries.push((name, value));
+        Ok(existing)
+    }
+
+    /// Adds `value` to the header with the given name, keeping any existing values.
+    pub fn append(
+        &mut self,
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<(), InvalidHeader> {
+        let (name, value) = validate(name.into(), value.into())?;
+        self.entries.push((name, value));
+        Ok(())
+    }
+
+    /// Adds a header which is already known to be valid (e.g. because it was parsed by
+    /// `httparse`).
+    pub(crate) fn append_unchecked(&mut self, name: String, value: String) {
+        self.entries.push((name, value));
+    }
+
+    /// Sets a header which is already known to be valid, replacing any existing values.
+    pub(crate) fn insert_unchecked(&mut self, name: String, value: String) {
+        self.remove(&name);
+        self.entries.push((name, value));
+    }
+
+    /// Removes every value of the header with the given name, returning the first of them.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let mut removed = None;
+        self.entries.retain(|(key, value)| {
+            if key.eq_ignore_ascii_case(name) {
+                removed.get_or_insert_with(|| value.clone());
+                false
+            } else {
+                true
+            }
+        });
+        removed
+    }
+
+    /// Iterates over every header (name and value), in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// The number of header values (counting each value of a header with several separately).
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether there are no headers.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl fmt::Debug for HeaderMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl IntoIterator for HeaderMap {
+    type Item = (String, String);
+    type IntoIter = std::vec::IntoIter<(String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl FromIterator<(String, String)> for HeaderMap {
+    /// Collects headers into a `HeaderMap`, appending them in order.
+    ///
+    /// # Panics
+    ///
+    /// If any of the headers is not valid (see [HeaderMap]).
+    fn from_iter<T: IntoIterator<Item = (String, String)>>(iter: T) -> Self {
+        let mut map = HeaderMap::new();
+        for (name, value) in iter {
+            map.append(name, value).expect("invalid header");
+        }
+        map
+    }
+}
+
+/// Checks that the header name and value can be written out safely.
+fn validate(name: String, value: String) -> Result<(String, String), InvalidHeader> {
+    if name.is_empty() || !name.bytes().all(is_token_char) {
+        return Err(InvalidHeader::InvalidName(name));
+    }
+    if value
+        .bytes()
+        .any(|byte| matches!(byte, b'\r' | b'\n' | b'\0'))
+    {
+        return Err(InvalidHeader::InvalidValue(name));
+    }
+    Ok((name, value))
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+/// An error encountered when adding a header to a [HeaderMap].
+pub enum InvalidHeader {
+    #[error("`{0}` is not a valid header name")]
+    /// The header name is empty, or contains characters which are not allowed in a token.
+    InvalidName(String),
+    #[error("the value of the `{0}` header contains a line break or null byte")]
+    /// The value of the header with this name contains a carriage return, line feed or null byte.
+    InvalidValue(String),
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HeaderMap, InvalidHeader};
+
+    #[lunatic::test]
+    fn test_case_insensitive_multi_valued() {
+        let mut headers = HeaderMap::new();
+        headers.append("Set-Cookie", "a=1").unwrap();
+        headers.append("Content-Type", "text/html").unwrap();
+        headers.append("set-cookie", "b=2").unwrap();
+
+        assert_eq!(headers.get("content-type"), Some("text/html"));
+        assert_eq!(
+            headers.get_all("SET-COOKIE").collect::<Vec<_>>(),
+            vec!["a=1", "b=2"]
+        );
+
+        assert_eq!(
+            headers.insert("SET-COOKIE", "c=3").unwrap(),
+            Some("a=1".to_string())
+        );
+        assert_eq!(
+            headers.iter().collect::<Vec<_>>(),
+            vec![("Content-Type", "text/html"), ("SET-COOKIE", "c=3")]
+        );
+
+        assert_eq!(
+            headers.remove("content-TYPE"),
+            Some("text/html".to_string())
+        );
+        assert!(!headers.contains_key("Content-Type"));
+        assert_eq!(headers.len(), 1);
+    }
+
+    #[lunatic::test]
+    fn test_get_list() {
+        let mut headers = HeaderMap::new();
+        headers.append("Connection", "keep-alive, Upgrade").unwrap();
+        headers.append("connection", " ,close").unwrap();
+        assert_eq!(
+            headers.get_list("Connection").collect::<Vec<_>>(),
+            vec!["keep-alive", "Upgrade", "close"]
+        );
+    }
+
+    #[lunatic::test]
+    fn test_rejects_injection() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            headers.insert("Location", "/\r\nSet-Cookie: evil=1"),
+            Err(InvalidHeader::InvalidValue("Location".to_string()))
+        );
+        assert_eq!(
+            headers.append("X-Evil\r\nSet-Cookie", "1"),
+            Err(InvalidHeader::InvalidName(
+                "X-Evil\r\nSet-Cookie".to_string()
+            ))
+        );
+        assert_eq!(
+            headers.append("", "1"),
+            Err(InvalidHeader::InvalidName(String::new()))
+        );
+        assert!(headers.is_empty());
+    }
+}
@@ -2,13 +2,11 @@
 
 #![deny(missing_debug_implementations, unused_must_use, missing_docs)]
 
-use std::{collections::HashMap, io::Write};
+use std::io::Write;
 
 #[cfg(test)]
 mod regressions;
 
-use body::{mime::HTML, Body};
-
 pub use anyhow;
 pub use lunatic;
 pub use request::Request;
@@ -17,37 +15,27 @@ pub use response::Response;
 use response::encoder::Encoder;
 
 pub mod body;
+pub mod compress;
+pub mod cookie;
 pub mod core;
+pub mod error;
+pub mod fs;
+pub mod headers;
 pub mod request;
 pub mod response;
+pub mod session;
 pub mod ws;
 
 /// Return an error 404 not found response.
+#[deprecated(note = "use `Response::not_found` (or `Response::error`) instead")]
 pub fn err_404() -> Response {
-    Response {
-        headers: {
-            let mut res = HashMap::new();
-            res.insert("Content-Type".to_string(), HTML.to_string());
-            res
-        },
-        body: Body::from_string("<h1>404: Not found</h1>".to_string()),
-        status: 404,
-        reason: "not found".to_string(),
-    }
+    Response::not_found()
 }
 
 /// Return a `400` error response.
+#[deprecated(note = "use `Response::bad_request` (or `Response::error`) instead")]
 pub fn err_400() -> Response {
-    Response {
-        headers: {
-            let mut res = HashMap::new();
-            res.insert("Content-Type".to_string(), HTML.to_string());
-            res
-        },
-        body: Body::from_string("<h1>400: bad request</h1>".to_string()),
-        status: 400,
-        reason: "bad request".to_string(),
-    }
+    Response::bad_request()
 }
 
 /// Write the given response to a writable TCP stream.
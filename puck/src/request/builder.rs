@@ -1,10 +1,10 @@
 //! A `Request` builder.
 
-use std::{collections::HashMap, convert::TryFrom};
+use std::convert::TryFrom;
 
 use url::Url;
 
-use crate::{body::Body, Request};
+use crate::{body::Body, core::router::match_url::Params, headers::HeaderMap, Request};
 
 use super::Method;
 
@@ -12,7 +12,7 @@ use super::Method;
 #[must_use]
 /// A struct used to build HTTP requests.
 pub struct RequestBuilder {
-    pub(crate) headers: HashMap<String, String>,
+    pub(crate) headers: HeaderMap,
     pub(crate) method: Option<Method>,
     pub(crate) body: Option<Body>,
     pub(crate) url: Url,
@@ -29,23 +29,32 @@ impl RequestBuilder {
     /// valid.
     pub fn try_new(url: impl AsRef<str>) -> Result<Self, url::ParseError> {
         Ok(Self {
-            headers: HashMap::new(),
+            headers: HeaderMap::new(),
             method: None,
             body: None,
             url: TryFrom::try_from(url.as_ref())?,
         })
     }
 
-    /// Add a new HTTP header to this `Request`.
+    /// Set a HTTP header on this `Request`, replacing any existing values of the header. This
+    /// method panics if the header is not valid (see [HeaderMap]).
     pub fn header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
-        self.headers.insert(key.into(), value.into());
+        self.headers
+            .insert(key, value)
+            .expect("`RequestBuilder` was given an invalid header");
         self
     }
 
     /// Add a series of new HTTP headers from the provided iterator to this request. This function
-    /// accepts anything implementing `IntoIterator<Item = (String, String)>`.
+    /// accepts anything implementing `IntoIterator<Item = (String, String)>`. Headers with the
+    /// same name are kept alongside each other, rather than replacing each other. This method
+    /// panics if any of the headers are not valid (see [HeaderMap]).
     pub fn headers(mut self, new_headers: impl IntoIterator<Item = (String, String)>) -> Self {
-        self.headers.extend(new_headers);
+        for (key, value) in new_headers {
+            self.headers
+                .append(key, value)
+                .expect("`RequestBuilder` was given an invalid header");
+        }
         self
     }
 
@@ -77,6 +86,10 @@ impl RequestBuilder {
                 .unwrap_or(Err(TryBuildError::MethodNotProvided))?,
             body: self.body.unwrap_or_else(Body::empty),
             url: self.url,
+            version: 1,
+            params: Params::default(),
+            base: String::new(),
+            session: None,
         })
     }
 }
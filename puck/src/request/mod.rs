@@ -1,19 +1,37 @@
 //! HTTP requests.
 
 use std::{
-    collections::HashMap,
+    fmt,
     io::{self, BufRead, BufReader, Read, Write},
-    str::Utf8Error,
+    str::{FromStr, Utf8Error},
 };
 
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
 use url::{ParseError, Url};
 
-use crate::body::Body;
+use crate::{
+    body::{
+        chunked::ChunkedDecoder,
+        form::Form,
+        mime::{Mime, BYTE_STREAM},
+        Body,
+    },
+    cookie::Cookies,
+    core::{
+        config::{self, ServerConfig},
+        router::match_url::Params,
+    },
+    headers::HeaderMap,
+    response::status::StatusCode,
+    session::Session,
+};
 
 pub mod builder;
 
-/// The maximum number of headers which Puck will parse.
-pub const MAX_HEADERS: usize = 20;
+/// The maximum number of headers which Puck will parse by default (see
+/// [ServerConfig::max_headers]).
+pub const MAX_HEADERS: usize = config::DEFAULT_MAX_HEADERS;
 
 /// The new line delimiter.
 pub const NEW_LINE: u8 = b'\n';
@@ -21,10 +39,21 @@ pub const NEW_LINE: u8 = b'\n';
 /// A HTTP request.
 #[derive(Debug)]
 pub struct Request {
-    pub(crate) headers: HashMap<String, String>,
+    pub(crate) headers: HeaderMap,
     pub(crate) method: Method,
     pub(crate) body: Body,
     pub(crate) url: Url,
+    /// The minor version of HTTP/1 the request was sent with (`0` for HTTP/1.0, `1` for
+    /// HTTP/1.1).
+    pub(crate) version: u8,
+    /// The parameters captured from the URL by the route which matched this request.
+    pub(crate) params: Params,
+    /// The prefixes which were stripped from the URL's path by nested routers.
+    pub(crate) base: String,
+    /// The client's session, if the request passed through the [Sessions] middleware.
+    ///
+    /// [Sessions]: crate::session::Sessions
+    pub(crate) session: Option<Session>,
 }
 
 impl Request {
@@ -44,36 +73,72 @@ impl Request {
     ///
     /// Note that if the request is empty, this will not return an error – instead it will return
     /// `Ok(None)`.
+    ///
+    /// The request line and headers must be within the default limits (see [ServerConfig]).
     pub fn parse(stream: impl Read + 'static) -> Result<Option<Self>, RequestParseError> {
-        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut reader = BufReader::with_capacity(10000, stream);
+
+        let mut req = if let Some(req) = Self::parse_head(&mut reader, &ServerConfig::default())? {
+            req
+        } else {
+            return Ok(None);
+        };
+
+        let body = match req.body_length()? {
+            BodyLength::Chunked => {
+                Body::from_reader(BufReader::new(ChunkedDecoder::new(reader)), None)
+            }
+            BodyLength::Known(length) => Body::from_reader(reader, Some(length)),
+            BodyLength::Unspecified => Body::from_reader(reader, None),
+        };
+        req.set_received_body(body);
+
+        Ok(Some(req))
+    }
+
+    /// Parse the request line and headers of a `Request` from the provided reader, leaving the
+    /// body (if any) unread. The `Request` returned has an empty body.
+    ///
+    /// This reads no further than the blank line which terminates the headers, so anything
+    /// buffered after that (e.g. the next of a series of pipelined requests) is left in `reader`.
+    /// No more than the limits in `config` allow is read into memory.
+    pub(crate) fn parse_head(
+        reader: &mut impl BufRead,
+        config: &ServerConfig,
+    ) -> Result<Option<Self>, RequestParseError> {
+        let mut headers = vec![httparse::EMPTY_HEADER; config.max_headers];
         let mut req = httparse::Request::new(&mut headers);
 
-        let mut reader = BufReader::with_capacity(10000, stream);
         let mut buf = Vec::new();
 
+        // blank lines before the request line are ignored
         loop {
-            let bytes_read = match reader.read_until(NEW_LINE, &mut buf) {
-                Ok(t) => t,
-                Err(e) => {
-                    return Err(From::from(e));
-                }
-            };
-            if bytes_read == 0 {
-                return Ok(None);
+            match read_line(reader, &mut buf, config.max_request_line)? {
+                Some(0) => return Ok(None),
+                Some(_) if buf == b"\r\n" || buf == b"\n" => buf.clear(),
+                Some(_) => break,
+                None => return Err(RequestParseError::RequestLineTooLong),
             }
-            // todo – drop requests for headers which are too large
-            let idx = buf.len() - 1;
-            if idx >= 3 && &buf[idx - 3..=idx] == b"\r\n\r\n" {
-                break;
+        }
+
+        let request_line = buf.len();
+        loop {
+            let remaining = config.max_header_bytes - (buf.len() - request_line);
+            let start = buf.len();
+            match read_line(reader, &mut buf, remaining)? {
+                Some(0) => return Ok(None),
+                Some(_) if &buf[start..] == b"\r\n" => break,
+                Some(_) => {}
+                None => return Err(RequestParseError::HeadersTooLarge),
             }
         }
 
         let _ = req.parse(&buf)?;
         let method = Method::new_from_str(req.method.ok_or(RequestParseError::MissingMethod)?);
         let headers = {
-            let mut map = HashMap::new();
+            let mut map = HeaderMap::new();
             for header in req.headers.iter() {
-                map.insert(
+                map.append_unchecked(
                     header.name.to_string(),
                     std::str::from_utf8(header.value)?.to_string(),
                 );
@@ -81,46 +146,97 @@ impl Request {
             map
         };
 
-        let url =
-            if let Some((_, host)) = headers.iter().find(|(k, _)| k.eq_ignore_ascii_case("host")) {
-                let url = req.path.ok_or(RequestParseError::InvalidUrl)?;
-                if url.starts_with("http://") || url.starts_with("https://") {
-                    Url::parse(url)
-                } else if url.starts_with('/') {
-                    Url::parse(&format!("http://{}{}", host, url))
-                } else if req.method.unwrap().eq_ignore_ascii_case("connect") {
-                    Url::parse(&format!("http://{}/", host))
-                } else {
-                    return Err(RequestParseError::InvalidUrl);
-                }
-                .map_err(|_| RequestParseError::InvalidUrl)?
+        let url = if let Some(host) = headers.get("host") {
+            let url = req.path.ok_or(RequestParseError::InvalidUrl)?;
+            if url.starts_with("http://") || url.starts_with("https://") {
+                Url::parse(url)
+            } else if url.starts_with('/') {
+                Url::parse(&format!("http://{}{}", host, url))
+            } else if req.method.unwrap().eq_ignore_ascii_case("connect") {
+                Url::parse(&format!("http://{}/", host))
             } else {
-                return Err(RequestParseError::MissingHeader("Host".to_string()));
-            };
-
-        let body = Body::from_reader(
-            reader,
-            headers
-                .iter()
-                .find(|(key, _)| key.eq_ignore_ascii_case("content-length"))
-                .and_then(|(_, len)| len.as_str().parse::<usize>().ok()),
-        );
+                return Err(RequestParseError::InvalidUrl);
+            }
+            .map_err(|_| RequestParseError::InvalidUrl)?
+        } else {
+            return Err(RequestParseError::MissingHeader("Host".to_string()));
+        };
 
         Ok(Some(Self {
             headers,
             method,
-            body,
+            body: Body::empty(),
             url,
+            version: req.version.unwrap_or(1),
+            params: Params::default(),
+            base: String::new(),
+            session: None,
         }))
     }
 
+    /// Works out how the length of this request's body is determined from its headers.
+    ///
+    /// If the `Transfer-Encoding` header is present, it takes precedence over `Content-Length`.
+    pub(crate) fn body_length(&self) -> Result<BodyLength, RequestParseError> {
+        if self.headers.contains_key("transfer-encoding") {
+            // the length can only be found if `chunked` is the final coding applied, and no other
+            // codings are decoded (so a body using them would reach the handler still encoded)
+            let mut codings = self.headers.get_list("transfer-encoding");
+            return match (codings.next(), codings.next()) {
+                (Some(coding), None) if coding.eq_ignore_ascii_case("chunked") => {
+                    Ok(BodyLength::Chunked)
+                }
+                _ => Err(RequestParseError::UnsupportedTransferEncoding),
+            };
+        }
+
+        // several `Content-Length` headers are only acceptable if they all agree
+        let mut length = None;
+        for value in self.headers.get_list("content-length") {
+            // `parse` would also accept a sign, which other servers (e.g. a proxy in front of
+            // this one) might not, so they would disagree about where the body ends
+            if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+                return Err(RequestParseError::InvalidContentLength);
+            }
+            let value = value
+                .parse::<usize>()
+                .map_err(|_| RequestParseError::InvalidContentLength)?;
+            if length
+                .replace(value)
+                .map_or(false, |length| length != value)
+            {
+                return Err(RequestParseError::InvalidContentLength);
+            }
+        }
+        Ok(length.map_or(BodyLength::Unspecified, BodyLength::Known))
+    }
+
+    /// Whether the client would like the connection to stay open once this `Request` has been
+    /// responded to.
+    ///
+    /// HTTP/1.1 connections are persistent unless the client sends `Connection: close`, whereas
+    /// HTTP/1.0 connections are closed unless the client sends `Connection: keep-alive`.
+    pub(crate) fn wants_keep_alive(&self) -> bool {
+        let has_token = |token: &str| {
+            self.headers
+                .get_list("connection")
+                .any(|value| value.eq_ignore_ascii_case(token))
+        };
+
+        if self.version == 0 {
+            has_token("keep-alive")
+        } else {
+            !has_token("close")
+        }
+    }
+
     /// Write this `Request` into the provided writer. Note that this will modify the `Request`
     /// in-place; specifically, it will empty the contents of this `Request`'s body.
     pub fn write(&mut self, write: &mut impl Write) -> io::Result<()> {
         self.method.write(write)?;
         write!(write, " {} ", self.url.path())?;
         write!(write, "HTTP/1.1\r\n")?;
-        for (key, value) in &self.headers {
+        for (key, value) in self.headers.iter() {
             write!(write, "{}: {}\r\n", key, value)?;
         }
         write!(write, "\r\n")?;
@@ -129,10 +245,15 @@ impl Request {
     }
 
     /// Get a reference to the request's headers.
-    pub fn headers(&self) -> &HashMap<String, String> {
+    pub fn headers(&self) -> &HeaderMap {
         &self.headers
     }
 
+    /// Get a mutable reference to the request's headers.
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        &mut self.headers
+    }
+
     /// Get a reference to the request's method.
     pub fn method(&self) -> &Method {
         &self.method
@@ -155,15 +276,91 @@ impl Request {
         self.replace_body(Body::empty())
     }
 
+    /// Sets the body of a request which has been received, with the MIME type named in the
+    /// request's `Content-Type` header.
+    pub(crate) fn set_received_body(&mut self, body: Body) {
+        let mime = self.content_type().unwrap_or(BYTE_STREAM);
+        self.body = body.with_mime(mime);
+    }
+
     fn copy_content_type_from_body(&mut self) {
         self.headers
-            .insert("Content-Type".into(), self.body.mime.to_string());
+            .insert_unchecked("Content-Type".into(), self.body.mime.to_string());
     }
 
     /// Get a reference to the request's url.
     pub fn url(&self) -> &Url {
         &self.url
     }
+
+    /// The request line (e.g. `GET /index.html HTTP/1.1`) which the request was received with.
+    pub(crate) fn request_line(&self) -> String {
+        let target = match self.url.query() {
+            Some(query) => format!("{}?{}", self.url.path(), query),
+            None => self.url.path().to_string(),
+        };
+        format!("{} {} HTTP/1.{}", self.method, target, self.version)
+    }
+
+    /// The MIME type of the request's body, parsed from its `Content-Type` header.
+    ///
+    /// Returns `None` if the header is missing or does not contain a valid MIME type.
+    pub fn content_type(&self) -> Option<Mime> {
+        self.headers.get("content-type")?.parse().ok()
+    }
+
+    /// The cookies sent with the request, parsed from its `Cookie` header(s).
+    pub fn cookies(&self) -> Cookies {
+        let mut cookies = Cookies::default();
+        for header in self.headers.get_all("cookie") {
+            cookies.extend(header);
+        }
+        cookies
+    }
+
+    /// Decode the query string of the request's URL (in the same way as form data).
+    pub fn query(&self) -> Form {
+        self.url
+            .query()
+            .map(|query| Form::parse(query.as_bytes()))
+            .unwrap_or_default()
+    }
+
+    /// Get the parameters captured from the URL by the route which matched this request.
+    ///
+    /// These are only set for routes constructed with [Route::capturing].
+    ///
+    /// [Route::capturing]: crate::core::router::Route::capturing
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    /// The path at which the router handling this request is mounted (see
+    /// [Router::nest](crate::core::router::Router::nest)), without a trailing slash. This is empty
+    /// unless the request was passed to a nested router.
+    ///
+    /// The prefix is stripped from the path of [Request::url], so the full path the client
+    /// requested is `base_path()` followed by `url().path()`.
+    pub fn base_path(&self) -> &str {
+        &self.base
+    }
+
+    /// The session of the client which sent the request. This is `None` unless the request was
+    /// passed through the [Sessions](crate::session::Sessions) middleware.
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+}
+
+/// How the length of a request's body is determined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BodyLength {
+    /// The body uses the `chunked` transfer coding.
+    Chunked,
+    /// The body is exactly this many bytes long.
+    Known(usize),
+    /// Neither `Content-Length` nor `Transfer-Encoding` was sent.
+    Unspecified,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -187,10 +384,52 @@ pub enum RequestParseError {
     /// The request method is missing.
     #[error("missing method")]
     MissingMethod,
+    /// The `Content-Length` header was not a valid length.
+    #[error("invalid content length")]
+    InvalidContentLength,
+    /// The body was sent using a transfer coding which is not supported.
+    #[error("unsupported transfer encoding")]
+    UnsupportedTransferEncoding,
+    /// The request line is longer than the limit (see [ServerConfig::max_request_line]).
+    #[error("the request line is too long")]
+    RequestLineTooLong,
+    /// The headers are longer than the limit (see [ServerConfig::max_header_bytes]).
+    #[error("the headers are too large")]
+    HeadersTooLarge,
+    /// The body is longer than the limit (see [ServerConfig::max_body_size]).
+    #[error("the body is too large")]
+    BodyTooLarge,
+    /// The client took too long to send the request (see [ServerConfig::head_timeout] and
+    /// [ServerConfig::body_timeout]).
+    #[error("timed out waiting for the request")]
+    TimedOut,
+}
+
+impl RequestParseError {
+    /// The status of the response which the client is sent when its request cannot be parsed:
+    /// `414 URI Too Long` if its request line is too long, `431 Request Header Fields Too Large`
+    /// if it sent too many headers (or headers which are too long), `413 Content Too Large` if
+    /// its body is too long, `408 Request Timeout` if it took too long to send the request,
+    /// `501 Not Implemented` if its body uses an unsupported transfer coding, and
+    /// `400 Bad Request` otherwise.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            RequestParseError::RequestLineTooLong => StatusCode::URI_TOO_LONG,
+            RequestParseError::CouldNotParse(httparse::Error::TooManyHeaders)
+            | RequestParseError::HeadersTooLarge => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            RequestParseError::BodyTooLarge => StatusCode::CONTENT_TOO_LARGE,
+            RequestParseError::TimedOut => StatusCode::REQUEST_TIMEOUT,
+            RequestParseError::UnsupportedTransferEncoding => StatusCode::NOT_IMPLEMENTED,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
 }
 
 impl From<std::io::Error> for RequestParseError {
     fn from(e: std::io::Error) -> Self {
+        if e.kind() == io::ErrorKind::TimedOut {
+            return Self::TimedOut;
+        }
         Self::IoError(e)
     }
 }
@@ -207,34 +446,256 @@ impl From<Utf8Error> for RequestParseError {
     }
 }
 
-#[derive(PartialEq, Eq, Clone, Debug)]
+#[derive(PartialEq, Eq, Clone, Debug, Hash)]
 /// The HTTP method (e.g. "GET" or "POST")
+///
+/// See [RFC 9110 section 9](https://www.rfc-editor.org/rfc/rfc9110#section-9). Note that methods
+/// are case-sensitive, so `get` is not the same method as `GET`.
 #[allow(missing_docs)]
 pub enum Method {
     Get,
     Post,
     Head,
+    Put,
+    Delete,
+    Patch,
+    Options,
+    Connect,
+    Trace,
     OtherMethod(String),
 }
 
 impl Method {
     /// Create a new method from the provided string.
+    ///
+    /// Unlike [FromStr], this does not check that the string is a valid method.
     pub fn new_from_str(str: &str) -> Self {
-        match str.to_ascii_lowercase().as_str() {
-            "get" => Self::Get,
-            "post" => Self::Post,
+        match str {
+            "GET" => Self::Get,
+            "POST" => Self::Post,
+            "HEAD" => Self::Head,
+            "PUT" => Self::Put,
+            "DELETE" => Self::Delete,
+            "PATCH" => Self::Patch,
+            "OPTIONS" => Self::Options,
+            "CONNECT" => Self::Connect,
+            "TRACE" => Self::Trace,
             _ => Self::OtherMethod(str.to_string()),
         }
     }
 
-    /// Write the given message to a TCP stream.
-    pub fn write(&self, write: &mut impl Write) -> io::Result<()> {
-        let to_write = match self {
+    /// The name of the method, as it appears in a request.
+    pub fn as_str(&self) -> &str {
+        match self {
             Method::Get => "GET",
             Method::Post => "POST",
-            Method::Head => "HEAD /",
+            Method::Head => "HEAD",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Patch => "PATCH",
+            Method::Options => "OPTIONS",
+            Method::Connect => "CONNECT",
+            Method::Trace => "TRACE",
             Method::OtherMethod(name) => name,
+        }
+    }
+
+    /// Whether the method is "safe", i.e. read-only, meaning that a client does not expect it to
+    /// change any state on the server (and it can, for example, be prefetched or cached).
+    ///
+    /// See [RFC 9110 section 9.2.1](https://www.rfc-editor.org/rfc/rfc9110#section-9.2.1).
+    pub fn is_safe(&self) -> bool {
+        matches!(
+            self,
+            Method::Get | Method::Head | Method::Options | Method::Trace
+        )
+    }
+
+    /// Whether sending a request with this method several times has the same effect as sending
+    /// it once (so that it can be retried automatically).
+    ///
+    /// See [RFC 9110 section 9.2.2](https://www.rfc-editor.org/rfc/rfc9110#section-9.2.2).
+    pub fn is_idempotent(&self) -> bool {
+        self.is_safe() || matches!(self, Method::Put | Method::Delete)
+    }
+
+    /// Write the given message to a TCP stream.
+    pub fn write(&self, write: &mut impl Write) -> io::Result<()> {
+        write!(write, "{}", self.as_str())
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Method {
+    type Err = InvalidMethod;
+
+    /// Parses a method, checking that it is a valid
+    /// [token](https://www.rfc-editor.org/rfc/rfc9110#section-5.6.2).
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        if !str.is_empty() && str.bytes().all(is_token_char) {
+            Ok(Self::new_from_str(str))
+        } else {
+            Err(InvalidMethod(str.to_string()))
+        }
+    }
+}
+
+impl Serialize for Method {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Method {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let method = String::deserialize(deserializer)?;
+        method.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[error("`{0}` is not a valid method")]
+/// The error returned when trying to parse a string which is not a valid method.
+pub struct InvalidMethod(String);
+
+/// Reads a line (including the line break) onto the end of `buf`, returning its length (which is
+/// `0` at the end of the stream), or `None` if it is longer than `max` bytes.
+fn read_line(
+    reader: &mut impl BufRead,
+    buf: &mut Vec<u8>,
+    max: usize,
+) -> io::Result<Option<usize>> {
+    let read = reader.take(max as u64 + 1).read_until(NEW_LINE, buf)?;
+    if read > max {
+        return Ok(None);
+    }
+    Ok(Some(read))
+}
+
+/// Whether the byte is a `tchar`, i.e. can be part of a token.
+pub(crate) fn is_token_char(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Cursor};
+
+    use super::*;
+
+    #[lunatic::test]
+    fn test_parse_pipelined_heads() {
+        let mut reader = BufReader::new(Cursor::new(
+            "GET /a HTTP/1.1\r\nHost: example.com\r\n\r\n\
+            GET /b HTTP/1.0\r\nHost: example.com\r\nConnection: Keep-Alive\r\n\r\n\
+            GET /c HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n",
+        ));
+
+        let a = Request::parse_head(&mut reader, &ServerConfig::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(a.url().path(), "/a");
+        assert!(a.wants_keep_alive());
+
+        let b = Request::parse_head(&mut reader, &ServerConfig::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(b.url().path(), "/b");
+        assert_eq!(b.version, 0);
+        assert!(b.wants_keep_alive());
+
+        let c = Request::parse_head(&mut reader, &ServerConfig::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(c.url().path(), "/c");
+        assert!(!c.wants_keep_alive());
+
+        assert!(Request::parse_head(&mut reader, &ServerConfig::default())
+            .unwrap()
+            .is_none());
+    }
+
+    #[lunatic::test]
+    fn test_parse_limits() {
+        let config = ServerConfig::default()
+            .max_request_line(32)
+            .max_headers(2)
+            .max_header_bytes(64);
+        let parse =
+            |head: String| Request::parse_head(&mut BufReader::new(Cursor::new(head)), &config);
+
+        let error = parse(format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(32))).unwrap_err();
+        assert_eq!(error.status(), StatusCode::URI_TOO_LONG);
+
+        let error =
+            parse("GET / HTTP/1.1\r\nHost: a\r\nA: 1\r\nB: 2\r\n\r\n".to_string()).unwrap_err();
+        assert_eq!(error.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+
+        let error = parse(format!(
+            "GET / HTTP/1.1\r\nHost: a\r\nA: {}\r\n\r\n",
+            "a".repeat(64)
+        ))
+        .unwrap_err();
+        assert_eq!(error.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+
+        let req = parse("\r\nGET / HTTP/1.1\r\nHost: a\r\nA: 1\r\n\r\n".to_string())
+            .unwrap()
+            .unwrap();
+        assert_eq!(req.headers().get("a"), Some("1"));
+    }
+
+    #[lunatic::test]
+    fn test_content_length() {
+        let body_length = |content_length: &str| {
+            let head = format!(
+                "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: {}\r\n\r\n",
+                content_length
+            );
+            Request::parse_head(
+                &mut BufReader::new(Cursor::new(head)),
+                &ServerConfig::default(),
+            )
+            .unwrap()
+            .unwrap()
+            .body_length()
         };
-        write!(write, "{}", to_write)
+
+        assert!(matches!(body_length("5"), Ok(BodyLength::Known(5))));
+        assert!(matches!(body_length("5, 5"), Ok(BodyLength::Known(5))));
+        for invalid in ["+5", "-5", "0x5", "5 5", "5, 6", "99999999999999999999"] {
+            let error = body_length(invalid).unwrap_err();
+            assert_eq!(error.status(), StatusCode::BAD_REQUEST, "{:?}", invalid);
+        }
+    }
+
+    #[lunatic::test]
+    fn test_parse_method() {
+        assert_eq!("GET".parse(), Ok(Method::Get));
+        assert_eq!("DELETE".parse(), Ok(Method::Delete));
+        assert_eq!(
+            "get".parse(),
+            Ok(Method::OtherMethod("get".to_string())),
+            "methods are case-sensitive"
+        );
+        assert_eq!(
+            "PROPFIND"
+                .parse::<Method>()
+                .map(|method| method.to_string()),
+            Ok("PROPFIND".to_string())
+        );
+        assert!("".parse::<Method>().is_err());
+        assert!("GET /".parse::<Method>().is_err());
+        assert!("G\r\nET".parse::<Method>().is_err());
+
+        assert!(Method::Head.is_safe());
+        assert!(!Method::Put.is_safe());
+        assert!(Method::Put.is_idempotent());
+        assert!(!Method::Post.is_idempotent());
+        assert!(!Method::Patch.is_idempotent());
     }
 }
@@ -1,17 +1,18 @@
 //! A `Response` builder.
 
-use std::collections::HashMap;
 use std::fmt::Debug;
 
-use crate::{body::Body, request::Method, Response};
+use crate::{body::Body, cookie::Cookie, headers::HeaderMap, request::Method, Response};
+
+use super::status::StatusCode;
 
 /// Builds `Response`s.
 #[derive(Default)]
 #[must_use]
 pub struct ResponseBuilder {
-    headers: HashMap<String, String>,
+    headers: HeaderMap,
     body: Option<Body>,
-    status: Option<u16>,
+    status: Option<StatusCode>,
     reason: Option<String>,
     method: Option<Method>,
 }
@@ -33,15 +34,42 @@ impl ResponseBuilder {
         Self::default()
     }
 
-    /// Set a header for this HTTP response.
+    /// Set a header for this HTTP response, replacing any existing values of the header. This
+    /// method panics if the header is not valid (see [HeaderMap]).
     pub fn header(mut self, key: impl ToString, value: impl ToString) -> Self {
-        self.headers.insert(key.to_string(), value.to_string());
+        self.headers
+            .insert(key.to_string(), value.to_string())
+            .expect("`ResponseBuilder` was given an invalid header");
+        self
+    }
+
+    /// Add a value to a header of this HTTP response, keeping any existing values (e.g. to send
+    /// several `Set-Cookie` headers). This method panics if the header is not valid (see
+    /// [HeaderMap]).
+    pub fn append_header(mut self, key: impl ToString, value: impl ToString) -> Self {
+        self.headers
+            .append(key.to_string(), value.to_string())
+            .expect("`ResponseBuilder` was given an invalid header");
         self
     }
 
-    /// Set a series of headers to this HTTP response from the provided iterator.
+    /// Add a series of headers to this HTTP response from the provided iterator. Headers with the
+    /// same name are kept alongside each other, rather than replacing each other. This method
+    /// panics if any of the headers are not valid (see [HeaderMap]).
     pub fn headers(mut self, new_headers: impl IntoIterator<Item = (String, String)>) -> Self {
-        self.headers.extend(new_headers);
+        for (key, value) in new_headers {
+            self.headers
+                .append(key, value)
+                .expect("`ResponseBuilder` was given an invalid header");
+        }
+        self
+    }
+
+    /// Add a `Set-Cookie` header, which sets the given cookie on the client. Each cookie is
+    /// sent in a header of its own.
+    pub fn cookie(mut self, cookie: Cookie) -> Self {
+        self.headers
+            .append_unchecked("Set-Cookie".to_string(), cookie.to_string());
         self
     }
 
@@ -51,20 +79,45 @@ impl ResponseBuilder {
         self
     }
 
-    /// Set the status for this `Response`.
+    /// Set the status for this `Response`, along with a custom reason phrase. This method panics
+    /// if the code is not a valid status code (see [StatusCode::from_u16]) or the reason contains
+    /// a line break.
+    ///
+    /// Usually [ResponseBuilder::status_code] (which uses the standard reason phrase) is simpler.
     pub fn status(mut self, code: u16, reason: impl ToString) -> Self {
-        self.status = Some(code);
-        self.reason = Some(reason.to_string());
+        let reason = reason.to_string();
+        assert!(
+            !reason.contains(&['\r', '\n'][..]),
+            "`ResponseBuilder` was given a reason containing a line break"
+        );
+        self.status = Some(
+            StatusCode::from_u16(code).expect("`ResponseBuilder` was given an invalid status code"),
+        );
+        self.reason = Some(reason);
+        self
+    }
+
+    /// Set the status for this `Response`. The reason phrase sent is the one registered for the
+    /// status code (e.g. `Not Found` for [StatusCode::NOT_FOUND]).
+    pub fn status_code(mut self, status: StatusCode) -> Self {
+        self.status = Some(status);
+        self.reason = None;
         self
     }
 
     /// Build this HTTP response. This function will not panic.
+    ///
+    /// If no status was set, the status is `200 OK`.
     pub fn build(self) -> Response {
+        let status = self.status.unwrap_or_default();
         Response {
             headers: self.headers,
             body: self.body.unwrap_or_else(Body::empty),
-            status: self.status.unwrap_or(200),
-            reason: self.reason.unwrap_or_default(),
+            status,
+            reason: self
+                .reason
+                .or_else(|| status.canonical_reason().map(ToString::to_string))
+                .unwrap_or_default(),
         }
     }
 }
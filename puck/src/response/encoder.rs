@@ -2,34 +2,172 @@
 
 use std::io::Write;
 
-use crate::Response;
+use crate::{body::chunked::ChunkedEncoder, Response};
+
+use super::status::StatusCode;
+
+/// How the body of a response is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    /// The response has no body, so none is written.
+    NoBody,
+    /// The body is written as it is.
+    Raw,
+    /// The body is written using the `chunked` transfer coding.
+    Chunked,
+}
 
 #[derive(Debug)]
 /// Encodes HTTP responses.
 pub struct Encoder {
     response: Response,
+    chunked: bool,
+    head: bool,
 }
 
 impl Encoder {
     /// Construct a new response encoder.
     pub fn new(response: Response) -> Self {
-        Self { response }
+        Self {
+            response,
+            chunked: true,
+            head: false,
+        }
+    }
+
+    /// Set whether a body whose length is not known in advance may be sent using the `chunked`
+    /// transfer coding (which HTTP/1.0 clients do not understand). If not, the body is written as
+    /// is, and the client will only know that it has ended once the connection is closed.
+    ///
+    /// This is allowed by default.
+    pub fn chunked(mut self, chunked: bool) -> Self {
+        self.chunked = chunked;
+        self
+    }
+
+    /// Set whether this is a response to a `HEAD` request, in which case the headers are written
+    /// exactly as they would be for a `GET` request, but the body is not written.
+    pub fn head(mut self, head: bool) -> Self {
+        self.head = head;
+        self
     }
 
     /// Write the current response to the given stream.
+    ///
+    /// Unless the response already specifies its own framing, a `Content-Length` header is sent
+    /// for bodies of known length, and other bodies are sent using the `chunked` transfer coding.
+    /// The body of an informational, `204 No Content` or `304 Not Modified` response is never
+    /// sent.
     pub fn write_tcp_stream(&mut self, mut stream: impl Write) -> std::io::Result<()> {
+        let framing = self.set_framing_headers();
+
         write!(
             stream,
             "HTTP/1.1 {} {}\r\n",
-            self.response.status, self.response.reason
+            self.response.status.as_u16(),
+            self.response.reason
         )?;
-        let mut headers = self.response.headers.iter().collect::<Vec<_>>();
-        headers.sort_unstable_by_key(|(h, _)| h.as_str());
-        for (header, value) in headers {
+        for (header, value) in self.response.headers.iter() {
             write!(stream, "{}: {}\r\n", header, value)?;
         }
         write!(stream, "\r\n")?;
-        std::io::copy(&mut self.response.body, &mut stream)?;
+        if self.head {
+            return Ok(());
+        }
+        match framing {
+            Framing::NoBody => {}
+            Framing::Raw => {
+                std::io::copy(&mut self.response.body, &mut stream)?;
+            }
+            Framing::Chunked => {
+                let mut encoder = ChunkedEncoder::new(&mut stream);
+                std::io::copy(&mut self.response.body, &mut encoder)?;
+                encoder.finish()?;
+            }
+        }
         Ok(())
     }
+
+    /// Adds the `Content-Length` or `Transfer-Encoding` header needed for the client to know
+    /// where the body ends, returning how the body should be written.
+    fn set_framing_headers(&mut self) -> Framing {
+        // informational, `204 No Content` and `304 Not Modified` responses never have a body
+        let status = self.response.status;
+        if status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::NOT_MODIFIED
+        {
+            return Framing::NoBody;
+        }
+
+        // a handler which sets `Transfer-Encoding` itself still leaves the chunking to us, as
+        // the body is read (and so written) unencoded
+        let headers = &self.response.headers;
+        if let Some(last) = headers.get_list("transfer-encoding").last() {
+            return if last.eq_ignore_ascii_case("chunked") {
+                Framing::Chunked
+            } else {
+                Framing::Raw
+            };
+        }
+        if headers.contains_key("content-length") {
+            return Framing::Raw;
+        }
+
+        match self.response.body.length {
+            Some(length) => {
+                self.response
+                    .headers
+                    .append_unchecked("Content-Length".to_string(), length.to_string());
+                Framing::Raw
+            }
+            None if self.chunked => {
+                self.response
+                    .headers
+                    .append_unchecked("Transfer-Encoding".to_string(), "chunked".to_string());
+                Framing::Chunked
+            }
+            None => Framing::Raw,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{response::status::StatusCode, Response};
+
+    use super::Encoder;
+
+    fn encode(response: Response) -> String {
+        let mut written = Vec::new();
+        Encoder::new(response)
+            .write_tcp_stream(&mut written)
+            .unwrap();
+        String::from_utf8(written).unwrap()
+    }
+
+    #[lunatic::test]
+    fn test_chunked_set_by_handler() {
+        let response = Response::build()
+            .header("Transfer-Encoding", "chunked")
+            .body("hello")
+            .build();
+        let written = encode(response);
+        assert!(written.ends_with("\r\n\r\n5\r\nhello\r\n0\r\n\r\n"));
+        assert_eq!(written.matches("Transfer-Encoding").count(), 1);
+    }
+
+    #[lunatic::test]
+    fn test_no_body() {
+        for status in [
+            StatusCode::CONTINUE,
+            StatusCode::NO_CONTENT,
+            StatusCode::NOT_MODIFIED,
+        ] {
+            let response = Response::build().status_code(status).body("hello").build();
+            let written = encode(response);
+            assert!(written.ends_with("\r\n\r\n"), "{}", written);
+            assert!(!written.contains("hello"));
+        }
+    }
 }
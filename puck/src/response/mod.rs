@@ -1,34 +1,41 @@
 //! HTTP responses.
 
-use std::{
-    collections::HashMap,
-    io::{self, BufRead, BufReader, Read},
-};
+use std::io::{self, BufRead, BufReader, Read};
+
+use serde::Serialize;
 
 use crate::{
-    body::Body,
+    body::{
+        chunked::ChunkedDecoder,
+        mime::{HTML, JSON},
+        Body,
+    },
+    cookie::Cookie,
+    headers::HeaderMap,
     request::{MAX_HEADERS, NEW_LINE},
 };
 
-use self::builder::ResponseBuilder;
+use self::{builder::ResponseBuilder, status::StatusCode};
 
 pub mod builder;
 pub mod encoder;
+pub(crate) mod range;
+pub mod status;
 
 /// A HTTP response.
 #[derive(Debug)]
 #[cfg_attr(feature = "fuzzing", derive(DefaultMutator, ToJson, FromJson))]
 pub struct Response {
-    pub(crate) headers: HashMap<String, String>,
+    pub(crate) headers: HeaderMap,
     pub(crate) body: Body,
-    pub(crate) status: u16,
+    pub(crate) status: StatusCode,
     pub(crate) reason: String,
 }
 
 impl Response {
     fn copy_content_type_from_body(&mut self) {
         self.headers
-            .insert("Content-Type".into(), self.body.mime.to_string());
+            .insert_unchecked("Content-Type".into(), self.body.mime.to_string());
     }
 
     /// Replaces the current body with an empty one and returns the current body.
@@ -43,11 +50,56 @@ impl Response {
         self.replace_body(Body::empty())
     }
 
+    /// Add a `Set-Cookie` header, which sets the given cookie on the client (see
+    /// [ResponseBuilder::cookie]).
+    pub fn add_cookie(&mut self, cookie: Cookie) {
+        self.headers
+            .append_unchecked("Set-Cookie".to_string(), cookie.to_string());
+    }
+
     /// Return a new `ResponseBuilder`, with which you can construct a new `Response`.
     pub fn build() -> ResponseBuilder {
         ResponseBuilder::new()
     }
 
+    /// Return an error response with the given status, and a short HTML page stating the status
+    /// code and its reason phrase.
+    pub fn error(status: StatusCode) -> Response {
+        Response::build()
+            .status_code(status)
+            .header("Content-Type", HTML)
+            .body(Body::from_string(format!("<h1>{}</h1>", status)))
+            .build()
+    }
+
+    /// Return a `200 OK` response containing `value` serialized as JSON, with the `Content-Type`
+    /// set to `application/json`.
+    ///
+    /// This returns an error if `value` cannot be serialized (e.g. if it is a map with keys which
+    /// are not strings).
+    pub fn json<T: Serialize + ?Sized>(value: &T) -> Result<Response, serde_json::Error> {
+        let json = serde_json::to_string(value)?;
+        Ok(Response::build()
+            .header("Content-Type", JSON)
+            .body(Body::from_string(json).with_mime(JSON))
+            .build())
+    }
+
+    /// Return a `400 Bad Request` error response (see [Response::error]).
+    pub fn bad_request() -> Response {
+        Response::error(StatusCode::BAD_REQUEST)
+    }
+
+    /// Return a `404 Not Found` error response (see [Response::error]).
+    pub fn not_found() -> Response {
+        Response::error(StatusCode::NOT_FOUND)
+    }
+
+    /// Return a `500 Internal Server Error` error response (see [Response::error]).
+    pub fn internal_server_error() -> Response {
+        Response::error(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
     /// Attempt to parse this `Response` from a stream (anything implementing `Read` that lives for
     /// `static`.) Note that if the response is empty, this function will return Ok(None), rather
     /// than an error.
@@ -78,9 +130,9 @@ impl Response {
         let _ = res.parse(&buf);
 
         let headers = {
-            let mut map = HashMap::new();
+            let mut map = HeaderMap::new();
             for header in res.headers.iter() {
-                map.insert(
+                map.append_unchecked(
                     header.name.to_string(),
                     match std::str::from_utf8(header.value) {
                         Ok(t) => t,
@@ -93,7 +145,7 @@ impl Response {
         };
 
         let status = if let Some(status) = res.code {
-            status
+            StatusCode::from_u16(status).map_err(|_| ParseResponseError::InvalidStatusCode)?
         } else {
             return Err(ParseResponseError::MissingStatusCode);
         };
@@ -104,13 +156,22 @@ impl Response {
             return Err(ParseResponseError::MissingReason);
         };
 
-        let body = Body::from_reader(
-            reader,
-            headers
-                .iter()
-                .find(|(key, _)| key.eq_ignore_ascii_case("content-type"))
-                .and_then(|(_, len)| len.as_str().parse::<usize>().ok()),
-        );
+        let chunked = headers
+            .get_list("transfer-encoding")
+            .last()
+            .map(|coding| coding.eq_ignore_ascii_case("chunked"))
+            .unwrap_or_default();
+
+        let body = if chunked {
+            Body::from_reader(BufReader::new(ChunkedDecoder::new(reader)), None)
+        } else {
+            Body::from_reader(
+                reader,
+                headers
+                    .get("content-length")
+                    .and_then(|len| len.trim().parse::<usize>().ok()),
+            )
+        };
 
         Ok(Some(Self {
             headers,
@@ -121,13 +182,18 @@ impl Response {
     }
 
     /// Get a reference to the response's headers.
-    pub fn headers(&self) -> &HashMap<String, String> {
+    pub fn headers(&self) -> &HeaderMap {
         &self.headers
     }
 
-    /// Get a reference to the response's status.
-    pub fn status(&self) -> &u16 {
-        &self.status
+    /// Get a mutable reference to the response's headers.
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        &mut self.headers
+    }
+
+    /// Get the response's status.
+    pub fn status(&self) -> StatusCode {
+        self.status
     }
 
     /// Get a reference to the response's reason.
@@ -144,6 +210,8 @@ pub enum ParseResponseError {
     IoError(io::Error),
     #[error("the status code was not supplied")]
     MissingStatusCode,
+    #[error("the status code was not valid")]
+    InvalidStatusCode,
     #[error("a reason was not supplied")]
     MissingReason,
     #[error("utf8 error")]
@@ -0,0 +1,480 @@
+//! Range requests, which ask for only part of a response's body (e.g. to resume a download, or
+//! to seek through a video).
+//!
+//! See [RFC 9110 section 14](https://www.rfc-editor.org/rfc/rfc9110#section-14).
+
+use std::{
+    convert::TryFrom,
+    fs::File,
+    io::{self, BufReader, Read},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    body::{mime::Mime, Body},
+    headers::HeaderMap,
+    request::Method,
+    Request,
+};
+
+use super::{status::StatusCode, Response};
+
+/// The most ranges which will be served in response to one request. Requests for more are
+/// answered with the whole body.
+const MAX_RANGES: usize = 32;
+
+/// A range of bytes within a body, including both the first and last byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    /// The offset of the first byte in the range.
+    pub start: u64,
+    /// The offset of the last byte in the range.
+    pub end: u64,
+}
+
+impl ByteRange {
+    fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+/// The parts of a body which a request asked for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Ranges {
+    /// The request did not ask for a range (or asked in a way which is ignored), so the whole
+    /// body should be sent.
+    Full,
+    /// The ranges which should be sent, sorted, with overlapping and adjacent ranges combined.
+    Partial(Vec<ByteRange>),
+    /// None of the ranges the request asked for are within the body.
+    Unsatisfiable,
+}
+
+/// Parses the value of a `Range` header, for a body which is `length` bytes long.
+///
+/// Headers which are not valid, or which use a unit other than `bytes`, are ignored (as
+/// [RFC 9110](https://www.rfc-editor.org/rfc/rfc9110#section-14.2) allows).
+pub(crate) fn parse_range(value: &str, length: u64) -> Ranges {
+    let specs = match value.trim().split_once('=') {
+        Some((unit, specs)) if unit.trim().eq_ignore_ascii_case("bytes") => specs,
+        _ => return Ranges::Full,
+    };
+
+    let mut ranges = Vec::new();
+    for spec in specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+    {
+        let (start, end) = match spec.split_once('-') {
+            Some((start, end)) => (start.trim(), end.trim()),
+            None => return Ranges::Full,
+        };
+        let parse = |number: &str| number.parse::<u64>().ok();
+
+        let range = if start.is_empty() {
+            // a suffix range, for the last `end` bytes
+            match parse(end) {
+                Some(0) => None,
+                Some(suffix) if length > 0 => Some(ByteRange {
+                    start: length.saturating_sub(suffix),
+                    end: length - 1,
+                }),
+                Some(_) => None,
+                None => return Ranges::Full,
+            }
+        } else {
+            let start = match parse(start) {
+                Some(start) => start,
+                None => return Ranges::Full,
+            };
+            let end = if end.is_empty() {
+                u64::MAX
+            } else {
+                match parse(end) {
+                    Some(end) if end >= start => end,
+                    _ => return Ranges::Full,
+                }
+            };
+            (start < length).then(|| ByteRange {
+                start,
+                end: end.min(length - 1),
+            })
+        };
+
+        ranges.extend(range);
+        if ranges.len() > MAX_RANGES {
+            return Ranges::Full;
+        }
+    }
+
+    if ranges.is_empty() {
+        return Ranges::Unsatisfiable;
+    }
+
+    // the body is read from start to end, so the ranges are sent in order (combining them where
+    // they overlap)
+    ranges.sort_by_key(|range| range.start);
+    let mut combined: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match combined.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) => {
+                last.end = last.end.max(range.end)
+            }
+            _ => combined.push(range),
+        }
+    }
+    Ranges::Partial(combined)
+}
+
+/// Works out which ranges of a `200 OK` response with the given headers (and a body `length`
+/// bytes long) `req` asked for, taking its `If-Range` header into account.
+pub(crate) fn requested_ranges(req: &Request, headers: &HeaderMap, length: u64) -> Ranges {
+    if req.method() != &Method::Get {
+        return Ranges::Full;
+    }
+    let range = match req.headers().get("range") {
+        Some(range) => range,
+        None => return Ranges::Full,
+    };
+
+    // `If-Range` asks for the range only if the body is the same as the one the client already
+    // has part of (otherwise the whole body is sent)
+    if let Some(if_range) = req.headers().get("if-range") {
+        let if_range = if_range.trim();
+        let unchanged = if if_range.starts_with('"') || if_range.starts_with("W/") {
+            // weak entity tags never match
+            headers.get("etag").map_or(false, |etag| {
+                !etag.starts_with("W/") && !if_range.starts_with("W/") && etag == if_range
+            })
+        } else {
+            let date = |value: &str| httpdate::parse_http_date(value).ok();
+            match (date(if_range), headers.get("last-modified").and_then(date)) {
+                (Some(if_range), Some(modified)) => if_range == modified,
+                _ => false,
+            }
+        };
+        if !unchanged {
+            return Ranges::Full;
+        }
+    }
+
+    parse_range(range, length)
+}
+
+/// The response to a request for ranges which are not within the body.
+pub(crate) fn unsatisfiable(length: u64) -> Response {
+    let mut response = Response::error(StatusCode::RANGE_NOT_SATISFIABLE);
+    response
+        .headers
+        .insert_unchecked("Content-Range".to_string(), format!("bytes */{}", length));
+    response
+}
+
+/// A source of a body which can skip forwards.
+pub(crate) trait Source: Read {
+    /// Skip over the next `n` bytes.
+    fn skip(&mut self, n: u64) -> io::Result<()> {
+        let skipped = io::copy(&mut self.take(n), &mut io::sink())?;
+        if skipped < n {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+}
+
+impl Source for Body {}
+
+impl Source for BufReader<File> {
+    fn skip(&mut self, n: u64) -> io::Result<()> {
+        self.seek_relative(n as i64)
+    }
+}
+
+/// Turns `response` (a `200 OK` response, with its body removed) into a `206 Partial Content`
+/// response containing the given ranges of `source`, which is `length` bytes long.
+///
+/// A single range is sent as is, with a `Content-Range` header. Several ranges are sent as a
+/// `multipart/byteranges` body, each part of which has its own `Content-Range` header.
+pub(crate) fn partial(
+    mut response: Response,
+    source: impl Source + 'static,
+    mime: &Mime,
+    ranges: Vec<ByteRange>,
+    length: u64,
+) -> Response {
+    response.status = StatusCode::PARTIAL_CONTENT;
+    response.reason = "Partial Content".to_string();
+    response.headers.remove("content-length");
+    response.headers.remove("transfer-encoding");
+
+    let segments = if let [range] = ranges[..] {
+        response.headers.insert_unchecked(
+            "Content-Range".to_string(),
+            format!("bytes {}-{}/{}", range.start, range.end, length),
+        );
+        vec![Segment::Range(range)]
+    } else {
+        let boundary = boundary();
+        response.headers.insert_unchecked(
+            "Content-Type".to_string(),
+            format!("multipart/byteranges; boundary={}", boundary),
+        );
+
+        let mut segments = Vec::with_capacity(ranges.len() * 2 + 1);
+        for range in ranges {
+            segments.push(Segment::Bytes(
+                format!(
+                    "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                    boundary, mime, range.start, range.end, length
+                )
+                .into_bytes(),
+            ));
+            segments.push(Segment::Range(range));
+        }
+        segments.push(Segment::Bytes(
+            format!("\r\n--{}--\r\n", boundary).into_bytes(),
+        ));
+        segments
+    };
+
+    let body_length = segments.iter().map(Segment::len).sum::<u64>();
+    // the `Content-Length` header is still exact when the length doesn't fit in a `usize` (which
+    // is only 32 bits on wasm32)
+    response.body = Body::from_reader(
+        BufReader::new(RangeReader {
+            source,
+            position: 0,
+            segments: segments.into_iter(),
+            current: None,
+        }),
+        usize::try_from(body_length).ok(),
+    );
+    response
+        .headers
+        .insert_unchecked("Content-Length".to_string(), body_length.to_string());
+    response
+}
+
+/// A boundary for a `multipart/byteranges` body.
+///
+/// This only needs to be unlikely to appear in the body, not unpredictable.
+fn boundary() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_nanos())
+        .unwrap_or_default();
+    format!("puck-byteranges-{:032x}", nanos)
+}
+
+enum Segment {
+    /// Bytes which are sent as is (the headers of each part of a `multipart/byteranges` body).
+    Bytes(Vec<u8>),
+    /// A range of the source.
+    Range(ByteRange),
+}
+
+impl Segment {
+    fn len(&self) -> u64 {
+        match self {
+            Segment::Bytes(bytes) => bytes.len() as u64,
+            Segment::Range(range) => range.len(),
+        }
+    }
+}
+
+/// Reads a series of segments, skipping over the parts of the source which are not in any of the
+/// ranges.
+struct RangeReader<S> {
+    source: S,
+    /// The offset in the source of the next byte which will be read from it.
+    position: u64,
+    segments: std::vec::IntoIter<Segment>,
+    /// The segment currently being read, and how many bytes of it have been read.
+    current: Option<(Segment, u64)>,
+}
+
+impl<S: Source> Read for RangeReader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let (segment, read) = match &mut self.current {
+                Some((segment, read)) if *read < segment.len() => (segment, read),
+                _ => match self.segments.next() {
+                    Some(segment) => {
+                        self.current = Some((segment, 0));
+                        continue;
+                    }
+                    None => return Ok(0),
+                },
+            };
+
+            let remaining = (segment.len() - *read).min(buf.len() as u64) as usize;
+            let n = match segment {
+                Segment::Bytes(bytes) => {
+                    let start = *read as usize;
+                    buf[..remaining].copy_from_slice(&bytes[start..start + remaining]);
+                    remaining
+                }
+                Segment::Range(range) => {
+                    let offset = range.start + *read;
+                    if self.position < offset {
+                        self.source.skip(offset - self.position)?;
+                        self.position = offset;
+                    }
+                    let n = self.source.read(&mut buf[..remaining])?;
+                    if n == 0 && remaining > 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    self.position += n as u64;
+                    n
+                }
+            };
+            *read += n as u64;
+            return Ok(n);
+        }
+    }
+}
+
+impl Response {
+    /// Answers a range request with the ranges of this response's body which `req` asked for.
+    ///
+    /// If `req` is a `GET` request with a `Range` header, and this is a `200 OK` response whose
+    /// body has a known length, then this returns a `206 Partial Content` response containing
+    /// those ranges (or `416 Range Not Satisfiable` if none of them are within the body). The
+    /// `If-Range` header is compared with this response's `ETag` or `Last-Modified` header.
+    /// Otherwise, this returns the response unchanged.
+    ///
+    /// Responses which this applies to are also given an `Accept-Ranges: bytes` header, to let
+    /// clients know that they can ask for ranges.
+    pub fn ranged(mut self, req: &Request) -> Response {
+        let length = match self.body.length {
+            Some(length) if self.status == StatusCode::OK => length as u64,
+            _ => return self,
+        };
+        self.headers
+            .insert_unchecked("Accept-Ranges".to_string(), "bytes".to_string());
+
+        match requested_ranges(req, &self.headers, length) {
+            Ranges::Full => self,
+            Ranges::Unsatisfiable => unsatisfiable(length),
+            Ranges::Partial(ranges) => {
+                let mime = self
+                    .headers
+                    .get("content-type")
+                    .and_then(|content_type| content_type.parse().ok())
+                    .unwrap_or_else(|| self.body.mime.clone());
+                let body = std::mem::replace(&mut self.body, Body::empty());
+                partial(self, body, &mime, ranges, length)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use crate::{body::mime::PLAIN, request::Method, response::status::StatusCode, Request};
+
+    use super::{parse_range, ByteRange, Ranges};
+    use crate::Response;
+
+    fn ranges(ranges: &[(u64, u64)]) -> Ranges {
+        Ranges::Partial(
+            ranges
+                .iter()
+                .map(|(start, end)| ByteRange {
+                    start: *start,
+                    end: *end,
+                })
+                .collect(),
+        )
+    }
+
+    #[lunatic::test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-499", 1000), ranges(&[(0, 499)]));
+        assert_eq!(parse_range("bytes=500-", 1000), ranges(&[(500, 999)]));
+        assert_eq!(parse_range("bytes=-300", 1000), ranges(&[(700, 999)]));
+        assert_eq!(parse_range("bytes=-3000", 1000), ranges(&[(0, 999)]));
+        assert_eq!(parse_range("bytes=900-2000", 1000), ranges(&[(900, 999)]));
+        assert_eq!(
+            parse_range("bytes=500-600, 0-10, 601-700,20-30", 1000),
+            ranges(&[(0, 10), (20, 30), (500, 700)])
+        );
+        assert_eq!(parse_range("bytes=1000-", 1000), Ranges::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 1000), Ranges::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), Ranges::Unsatisfiable);
+        assert_eq!(parse_range("bytes=5-1", 1000), Ranges::Full);
+        assert_eq!(parse_range("bytes=a-b", 1000), Ranges::Full);
+        assert_eq!(parse_range("lines=1-2", 1000), Ranges::Full);
+    }
+
+    fn get(range: &str, if_range: Option<&str>) -> Request {
+        let mut builder = Request::build("http://example.com/")
+            .method(Method::Get)
+            .header("Range", range);
+        if let Some(if_range) = if_range {
+            builder = builder.header("If-Range", if_range);
+        }
+        builder.build()
+    }
+
+    fn text() -> Response {
+        Response::build()
+            .header("Content-Type", PLAIN)
+            .header("ETag", "\"v1\"")
+            .body("0123456789")
+            .build()
+    }
+
+    #[lunatic::test]
+    fn test_single_range() {
+        let mut response = text().ranged(&get("bytes=2-4", None));
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            response.headers().get("Content-Range"),
+            Some("bytes 2-4/10")
+        );
+        assert_eq!(response.headers().get("Accept-Ranges"), Some("bytes"));
+        let mut body = String::new();
+        response.body.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "234");
+    }
+
+    #[lunatic::test]
+    fn test_multiple_ranges() {
+        let mut response = text().ranged(&get("bytes=7-,0-1", Some("\"v1\"")));
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        let content_type = response.headers().get("Content-Type").unwrap().to_string();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+
+        let mut body = String::new();
+        response.body.read_to_string(&mut body).unwrap();
+        assert_eq!(
+            body,
+            format!(
+                "\r\n--{0}\r\nContent-Type: text/plain;charset=utf-8\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+                \r\n--{0}\r\nContent-Type: text/plain;charset=utf-8\r\nContent-Range: bytes 7-9/10\r\n\r\n789\
+                \r\n--{0}--\r\n",
+                boundary
+            )
+        );
+        assert_eq!(
+            response.headers().get("Content-Length"),
+            Some(body.len().to_string().as_str())
+        );
+    }
+
+    #[lunatic::test]
+    fn test_unsatisfiable_and_if_range() {
+        let response = text().ranged(&get("bytes=10-", None));
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers().get("Content-Range"), Some("bytes */10"));
+
+        let response = text().ranged(&get("bytes=0-1", Some("\"v0\"")));
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,493 @@
+//! Server-side sessions.
+//!
+//! Session data is kept in a [SessionStore], a process which is started once (before the server
+//! starts) and shared by every connection. The [Sessions] middleware gives each client a cookie
+//! holding the ID of its session, and makes the session available to handlers through
+//! [Request::session].
+//!
+//! ```ignore
+//! let store = SessionStore::start(Duration::from_secs(60 * 60), None);
+//!
+//! // in `MakeRouter::make_router`
+//! Router::new()
+//!     .post(Match::new().at(path("login")), |req: Request, stream: Stream, _| {
+//!         let session = req.session().unwrap();
+//!         // always give the client a new session ID when it logs in
+//!         session.regenerate();
+//!         session.insert("user", &user_id);
+//!         stream.respond(Response::build().build()).unwrap()
+//!     })
+//!     .wrap(Sessions::new(store))
+//! ```
+//!
+//! Every read or write is a request to the store, so handlers (or liveview components, using
+//! [SessionRef]) handling different connections of the same client always see each other's
+//! changes.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::Rc,
+    time::{Duration, SystemTime},
+};
+
+use lunatic::process::{
+    AbstractProcess, Message, ProcessMessage, ProcessRef, ProcessRequest, Request as _,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    cookie::{random_bytes, Cookie, SameSite},
+    core::{
+        router::{Handler, Middleware},
+        Stream, UsedStream,
+    },
+    Request,
+};
+
+/// The name of the cookie which holds the session ID, unless another is chosen with
+/// [Sessions::cookie_name].
+pub const DEFAULT_COOKIE_NAME: &str = "puck_session";
+
+/// A process which stores the data of every session.
+///
+/// Start it (once) with [StartProcess::start](lunatic::process::StartProcess::start), passing
+/// how long sessions last without being used before they expire.
+#[derive(Debug)]
+pub struct SessionStore {
+    sessions: HashMap<String, Entry>,
+    ttl: Duration,
+}
+
+#[derive(Debug)]
+struct Entry {
+    data: HashMap<String, String>,
+    expires: SystemTime,
+}
+
+impl SessionStore {
+    /// The session with the given ID, if it exists and has not expired.
+    fn live(&mut self, id: &str) -> Option<&mut Entry> {
+        let now = SystemTime::now();
+        match self.sessions.get(id) {
+            Some(entry) if entry.expires <= now => {
+                self.sessions.remove(id);
+                None
+            }
+            _ => {
+                let ttl = self.ttl;
+                self.sessions.get_mut(id).map(|entry| {
+                    entry.expires = now + ttl;
+                    entry
+                })
+            }
+        }
+    }
+}
+
+impl AbstractProcess for SessionStore {
+    type Arg = Duration;
+    type State = Self;
+
+    fn init(_: ProcessRef<Self>, ttl: Duration) -> Self {
+        SessionStore {
+            sessions: HashMap::new(),
+            ttl,
+        }
+    }
+}
+
+/// Checks that a session exists (and has not expired), extending its lifetime.
+#[derive(Serialize, Deserialize)]
+struct Touch(String);
+
+impl ProcessRequest<Touch> for SessionStore {
+    type Response = bool;
+
+    fn handle(state: &mut Self::State, Touch(id): Touch) -> bool {
+        state.live(&id).is_some()
+    }
+}
+
+/// Creates a new, empty session.
+#[derive(Serialize, Deserialize)]
+struct Create(String);
+
+impl ProcessMessage<Create> for SessionStore {
+    fn handle(state: &mut Self::State, Create(id): Create) {
+        // this is a convenient time to clear out sessions which have expired
+        let now = SystemTime::now();
+        state.sessions.retain(|_, entry| entry.expires > now);
+        state.sessions.insert(
+            id,
+            Entry {
+                data: HashMap::new(),
+                expires: now + state.ttl,
+            },
+        );
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Get(String, String);
+
+impl ProcessRequest<Get> for SessionStore {
+    type Response = Option<String>;
+
+    fn handle(state: &mut Self::State, Get(id, key): Get) -> Option<String> {
+        state.live(&id)?.data.get(&key).cloned()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Insert(String, String, String);
+
+impl ProcessMessage<Insert> for SessionStore {
+    fn handle(state: &mut Self::State, Insert(id, key, value): Insert) {
+        if let Some(entry) = state.live(&id) {
+            entry.data.insert(key, value);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Remove(String, String);
+
+impl ProcessMessage<Remove> for SessionStore {
+    fn handle(state: &mut Self::State, Remove(id, key): Remove) {
+        if let Some(entry) = state.live(&id) {
+            entry.data.remove(&key);
+        }
+    }
+}
+
+/// Moves a session's data to a new ID (or creates an empty session with that ID, if the old one
+/// has expired).
+#[derive(Serialize, Deserialize)]
+struct Rename(String, String);
+
+impl ProcessMessage<Rename> for SessionStore {
+    fn handle(state: &mut Self::State, Rename(old, new): Rename) {
+        if state.live(&old).is_some() {
+            let entry = state.sessions.remove(&old).unwrap();
+            state.sessions.insert(new, entry);
+        } else {
+            <Self as ProcessMessage<Create>>::handle(state, Create(new));
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Destroy(String);
+
+impl ProcessMessage<Destroy> for SessionStore {
+    fn handle(state: &mut Self::State, Destroy(id): Destroy) {
+        state.sessions.remove(&id);
+    }
+}
+
+/// A random, unguessable session ID.
+fn new_id() -> String {
+    let mut bytes = [0; 32];
+    random_bytes(&mut bytes);
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+/// A reference to a session in a [SessionStore], which can be sent to other processes (such as
+/// the process running a liveview component).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionRef {
+    id: String,
+    store: ProcessRef<SessionStore>,
+}
+
+impl SessionRef {
+    /// The ID of the session.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The value stored under `key`, or `None` if there is no such value (or it cannot be
+    /// deserialized as a `T`), or the session has expired.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let value = self.store.request(Get(self.id.clone(), key.to_string()))?;
+        serde_json::from_str(&value).ok()
+    }
+
+    /// Store `value` under `key`, replacing any existing value. This does nothing if the session
+    /// has expired.
+    ///
+    /// This panics if `value` cannot be serialized as JSON.
+    pub fn insert<T: Serialize + ?Sized>(&self, key: &str, value: &T) {
+        let value = serde_json::to_string(value).expect("failed to serialize a session value");
+        self.store
+            .send(Insert(self.id.clone(), key.to_string(), value));
+    }
+
+    /// Remove the value stored under `key`.
+    pub fn remove(&self, key: &str) {
+        self.store.send(Remove(self.id.clone(), key.to_string()));
+    }
+
+    /// Whether the session still exists (i.e. it has not expired or been destroyed). This
+    /// extends the session's lifetime, as any other use of it does.
+    pub fn is_live(&self) -> bool {
+        self.store.request(Touch(self.id.clone()))
+    }
+}
+
+/// The session of the client which sent a request (see [Request::session]).
+///
+/// A session is only created (and the client sent a cookie) once something is stored in it, so
+/// clients which never log in do not take up space in the store.
+#[derive(Debug, Clone)]
+pub struct Session {
+    inner: Rc<RefCell<SessionState>>,
+}
+
+#[derive(Debug)]
+struct SessionState {
+    store: ProcessRef<SessionStore>,
+    /// The client's session, if it has one.
+    current: Option<SessionRef>,
+    /// Whether the session cookie needs to be set (or removed).
+    changed: bool,
+}
+
+impl Session {
+    fn new(store: ProcessRef<SessionStore>, current: Option<SessionRef>) -> Session {
+        Session {
+            inner: Rc::new(RefCell::new(SessionState {
+                store,
+                current,
+                changed: false,
+            })),
+        }
+    }
+
+    /// A reference to the session, which can be sent to other processes (e.g. to a liveview
+    /// component). This is `None` if the client does not have a session yet.
+    pub fn reference(&self) -> Option<SessionRef> {
+        self.inner.borrow().current.clone()
+    }
+
+    /// The value stored under `key` (see [SessionRef::get]).
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        self.inner.borrow().current.as_ref()?.get(key)
+    }
+
+    /// Store `value` under `key`, starting a new session if the client does not have one (see
+    /// [SessionRef::insert]).
+    pub fn insert<T: Serialize + ?Sized>(&self, key: &str, value: &T) {
+        self.current().insert(key, value)
+    }
+
+    /// Remove the value stored under `key`.
+    pub fn remove(&self, key: &str) {
+        if let Some(current) = &self.inner.borrow().current {
+            current.remove(key);
+        }
+    }
+
+    /// Give the session a new ID, keeping its data. This should be done whenever the user logs
+    /// in (or their privileges change), so that an attacker who planted a session ID in the
+    /// client before it logged in cannot use that ID afterwards.
+    pub fn regenerate(&self) {
+        let mut inner = self.inner.borrow_mut();
+        let id = new_id();
+        match &mut inner.current {
+            Some(current) => {
+                current.store.send(Rename(current.id.clone(), id.clone()));
+                current.id = id;
+            }
+            None => {
+                inner.store.send(Create(id.clone()));
+                inner.current = Some(SessionRef {
+                    id,
+                    store: inner.store.clone(),
+                });
+            }
+        }
+        inner.changed = true;
+    }
+
+    /// Delete the session and its data (e.g. when the user logs out), and tell the client to
+    /// remove its session cookie.
+    pub fn destroy(&self) {
+        let mut inner = self.inner.borrow_mut();
+        if let Some(current) = inner.current.take() {
+            current.store.send(Destroy(current.id));
+            inner.changed = true;
+        }
+    }
+
+    /// The client's session, starting a new one if it does not have one.
+    fn current(&self) -> SessionRef {
+        let mut inner = self.inner.borrow_mut();
+        if inner.current.is_none() {
+            let id = new_id();
+            inner.store.send(Create(id.clone()));
+            inner.current = Some(SessionRef {
+                id,
+                store: inner.store.clone(),
+            });
+            inner.changed = true;
+        }
+        inner.current.clone().unwrap()
+    }
+}
+
+/// Middleware which loads the session of the client sending each request (see the
+/// [module documentation](self)).
+///
+/// The session cookie is `HttpOnly`, has `SameSite=Lax`, and lasts until the browser is closed
+/// (sessions also expire on the server once they have not been used for the lifetime the
+/// [SessionStore] was started with).
+#[derive(Debug, Clone)]
+pub struct Sessions {
+    store: ProcessRef<SessionStore>,
+    cookie_name: String,
+    path: String,
+    secure: bool,
+}
+
+impl Sessions {
+    /// Create the middleware, storing sessions in `store`.
+    pub fn new(store: ProcessRef<SessionStore>) -> Sessions {
+        Sessions {
+            store,
+            cookie_name: DEFAULT_COOKIE_NAME.to_string(),
+            path: "/".to_string(),
+            secure: false,
+        }
+    }
+
+    /// Set the name of the cookie which holds the session ID. This method panics if the name is
+    /// not a valid cookie name.
+    pub fn cookie_name(mut self, cookie_name: impl Into<String>) -> Sessions {
+        let cookie_name = cookie_name.into();
+        assert!(
+            Cookie::try_new(cookie_name.as_str(), "").is_ok(),
+            "`Sessions` was given an invalid cookie name"
+        );
+        self.cookie_name = cookie_name;
+        self
+    }
+
+    /// Set the path which the session cookie is sent to (by default, `/`).
+    pub fn path(mut self, path: impl Into<String>) -> Sessions {
+        self.path = path.into();
+        self
+    }
+
+    /// Set whether the session cookie should only be sent over HTTPS. This should be enabled
+    /// whenever the site is served over HTTPS.
+    pub fn secure(mut self, secure: bool) -> Sessions {
+        self.secure = secure;
+        self
+    }
+
+    fn cookie(&self, current: Option<&SessionRef>) -> Cookie {
+        let cookie = match current {
+            Some(current) => Cookie::new(self.cookie_name.as_str(), current.id.as_str()),
+            None => Cookie::removal(self.cookie_name.as_str()),
+        };
+        cookie
+            .path(self.path.as_str())
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .secure(self.secure)
+    }
+}
+
+impl<STATE> Middleware<STATE> for Sessions {
+    fn handle(
+        &self,
+        mut req: Request,
+        stream: Stream,
+        state: STATE,
+        next: &dyn Handler<STATE>,
+    ) -> UsedStream {
+        // IDs which the store does not know about are ignored (rather than used for a new
+        // session), so that clients cannot choose their own session IDs
+        let cookies = req.cookies();
+        let current = cookies
+            .get(&self.cookie_name)
+            .filter(|id| self.store.request(Touch(id.to_string())))
+            .map(|id| SessionRef {
+                id: id.to_string(),
+                store: self.store.clone(),
+            });
+        let session = Session::new(self.store.clone(), current);
+        req.session = Some(session.clone());
+
+        let this = self.clone();
+        let stream = stream.map_response(move |mut response| {
+            let inner = session.inner.borrow();
+            if inner.changed {
+                response.add_cookie(this.cookie(inner.current.as_ref()));
+            }
+            drop(inner);
+            response
+        });
+        next.handle(req, stream, state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use lunatic::process::StartProcess;
+
+    use super::{Session, SessionRef, SessionStore};
+
+    #[lunatic::test]
+    fn test_session() {
+        let store = SessionStore::start(Duration::from_secs(60), None);
+
+        let session = Session::new(store.clone(), None);
+        assert_eq!(session.get::<u32>("user"), None);
+        assert!(session.reference().is_none());
+        session.insert("user", &7u32);
+        assert_eq!(session.get::<u32>("user"), Some(7));
+        assert!(session.inner.borrow().changed);
+
+        // other processes see the same data
+        let reference = session.reference().unwrap();
+        assert_eq!(reference.get::<u32>("user"), Some(7));
+        reference.insert("theme", "dark");
+        assert_eq!(session.get::<String>("theme"), Some("dark".to_string()));
+
+        session.regenerate();
+        let regenerated = session.reference().unwrap();
+        assert_ne!(regenerated.id(), reference.id());
+        assert!(!reference.is_live());
+        assert_eq!(regenerated.get::<u32>("user"), Some(7));
+
+        session.remove("user");
+        assert_eq!(session.get::<u32>("user"), None);
+
+        session.destroy();
+        assert!(session.reference().is_none());
+        assert!(!regenerated.is_live());
+
+        // IDs which the store did not issue are not accepted
+        let forged = SessionRef {
+            id: "forged".to_string(),
+            store: store.clone(),
+        };
+        forged.insert("user", &1u32);
+        assert!(!forged.is_live());
+        assert_eq!(forged.get::<u32>("user"), None);
+    }
+
+    #[lunatic::test]
+    fn test_expiry() {
+        let store = SessionStore::start(Duration::from_millis(0), None);
+        let session = Session::new(store, None);
+        session.insert("user", &7u32);
+        assert_eq!(session.get::<u32>("user"), None);
+        assert!(!session.reference().unwrap().is_live());
+    }
+}
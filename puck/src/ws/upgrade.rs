@@ -4,20 +4,17 @@ use base64::encode;
 use log::trace;
 use sha1::{Digest, Sha1};
 
-use crate::{err_400, write_response, Response};
+use crate::{response::status::StatusCode, write_response, Response};
 
 /// Compute whether or not this request can be upgraded.
 pub fn should_upgrade(req: &crate::Request) -> bool {
     req.headers
-        .get("Upgrade")
-        .map(|val| val.to_ascii_lowercase() == "websocket")
-        .unwrap_or_default()
-        // todo: read the spec to check if the check for the upgrade header is correct!
+        .get_list("Upgrade")
+        .any(|protocol| protocol.eq_ignore_ascii_case("websocket"))
         && req
             .headers
-            .get("Connection")
-            .map(|val| val.to_ascii_lowercase().contains("upgrade"))
-            .unwrap_or_default()
+            .get_list("Connection")
+            .any(|option| option.eq_ignore_ascii_case("upgrade"))
 }
 
 /// The 'magic string' used to upgrade WebSocket connections.
@@ -32,19 +29,19 @@ pub fn perform_upgrade(req: &crate::Request, stream: impl Write) -> bool {
         Some(t) => t,
         None => {
             trace!("Rejecting WebSocket upgrade because of missing `Sec-WebSocket-Key` header.");
-            write_response(err_400(), stream);
+            write_response(Response::bad_request(), stream);
             return false;
         }
     };
 
-    let result = compute_accept_header(key.clone());
+    let result = compute_accept_header(key.trim().to_string());
 
     write_response(
         Response::build()
             .header("Sec-WebSocket-Accept", result)
             .header("Upgrade", "websocket")
             .header("Connection", "Upgrade")
-            .status(101, "Web Socket Protocol Handshake")
+            .status_code(StatusCode::SWITCHING_PROTOCOLS)
             .build(),
         stream,
     );
@@ -63,7 +60,31 @@ fn compute_accept_header(key: String) -> String {
 
 #[cfg(test)]
 mod test {
-    use crate::ws::upgrade::compute_accept_header;
+    use crate::{
+        request::Method,
+        ws::upgrade::{compute_accept_header, should_upgrade},
+        Request,
+    };
+
+    #[lunatic::test]
+    fn test_should_upgrade() {
+        let req = Request::build("http://example.com/ws")
+            .method(Method::Get)
+            .header("upgrade", "WebSocket")
+            .headers(vec![
+                ("connection".to_string(), "keep-alive".to_string()),
+                ("Connection".to_string(), "Upgrade".to_string()),
+            ])
+            .build();
+        assert!(should_upgrade(&req));
+
+        let req = Request::build("http://example.com/ws")
+            .method(Method::Get)
+            .header("Upgrade", "websocket")
+            .header("Connection", "keep-alive")
+            .build();
+        assert!(!should_upgrade(&req));
+    }
 
     #[lunatic::test]
     fn test_compute_upgrade_header() {
@@ -1,9 +1,17 @@
-use std::io::Write;
+use std::{
+    cell::Cell,
+    io::{self, Read, Write},
+    rc::Rc,
+    time::Duration,
+};
 
 use log::trace;
-use lunatic::net::TcpStream;
+use lunatic::{net::TcpStream, Mailbox, Process, Tag};
 
-use crate::core::UsedStream;
+use crate::core::{
+    timeout::{ReadTimeout, TimedReader},
+    UsedStream,
+};
 
 use super::{
     frame::Frame,
@@ -11,6 +19,37 @@ use super::{
     send::{self, send_frame, SendFrameError},
 };
 
+/// How often a process waiting for a message from the client checks whether the server is
+/// shutting down (see [notify_going_away]).
+const GOING_AWAY_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The tag of the message which tells a process that the server is shutting down.
+fn going_away_tag() -> Tag {
+    // the tags from 64 to 128 are set aside for applications, and this one is never used for
+    // anything else
+    Tag::special(64).unwrap()
+}
+
+/// Tells `process` (which upgraded a connection to a WebSocket connection) that the server is
+/// shutting down. The next time it waits for a message from the client, it sends the client a
+/// close frame saying that the server is going away.
+///
+/// This is done by the process itself, rather than by whichever process finds out about the
+/// shutdown, so that the close frame cannot end up in the middle of another frame.
+pub(crate) fn notify_going_away(process: &Process<()>) {
+    process.tag_send(going_away_tag(), ());
+}
+
+/// Whether this process has been told that the server is shutting down.
+fn going_away() -> bool {
+    // the message is only ever received by its tag, so this does not interfere with the
+    // process's own mailbox
+    let mailbox: Mailbox<()> = unsafe { Mailbox::new() };
+    mailbox
+        .tag_receive_timeout(Some(&[going_away_tag()]), Duration::ZERO)
+        .is_ok()
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 /// A WebSocket connection over a duplex stream.
 ///
@@ -27,6 +66,9 @@ pub struct WebSocket {
 pub enum WebSocketState {
     /// The connection is open.
     Open,
+    /// The server has sent a close frame (because it is shutting down), and is waiting for the
+    /// client's.
+    Closing,
     /// The connection has been closed.
     Closed,
 }
@@ -61,7 +103,7 @@ impl WebSocket {
             WebSocketState::Open => {
                 send_close_frame(self.stream.clone());
             }
-            WebSocketState::Closed => {}
+            WebSocketState::Closing | WebSocketState::Closed => {}
         };
 
         Ok(UsedStream {
@@ -77,6 +119,103 @@ impl WebSocket {
             state: self.state,
         }
     }
+
+    /// Waits for the client to start sending a frame, returning its first byte (or `None` if the
+    /// client closed the connection).
+    ///
+    /// While waiting, this sends the client a close frame if the server starts shutting down.
+    fn wait_for_frame(&mut self) -> io::Result<Option<u8>> {
+        let mut reader = TimedReader::new(
+            self.stream.clone(),
+            ReadTimeout::Body {
+                timeout: GOING_AWAY_POLL_INTERVAL,
+                min_rate: None,
+            },
+        );
+        let mut byte = [0];
+        loop {
+            match reader.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(error) if error.kind() == io::ErrorKind::TimedOut => {
+                    if let WebSocketState::Open = self.state {
+                        if going_away() {
+                            // the client replies with its own close frame, which ends the loop
+                            // reading messages
+                            send_going_away(self.stream.clone())
+                                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+                            self.state = WebSocketState::Closing;
+                        }
+                    }
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Parses the message which `stream` starts with.
+    fn next_message(&mut self, stream: Resumed) -> Result<Message, NextMessageError> {
+        match Message::next(stream) {
+            Ok(msg) => {
+                if let Message::Ping(ref payload) = msg {
+                    send_frame(
+                        self.stream.clone(),
+                        Frame {
+                            fin: true,
+                            rsv1: false,
+                            rsv2: false,
+                            rsv3: false,
+                            op_code: super::frame::OpCode::Pong,
+                            decoded: payload.clone().unwrap_or_default(),
+                        },
+                    )
+                    .expect("failed to send pong");
+                }
+                Ok(msg)
+            }
+            Err(e) => match e {
+                super::message::DecodeMessageError::ClientProtocolViolationError => {
+                    Err(NextMessageError::ClientError)
+                }
+                super::message::DecodeMessageError::ClientSentCloseFrame => {
+                    // the client's close frame may be the reply to one the server sent
+                    if let WebSocketState::Open = self.state {
+                        send_close_frame(self.stream.clone());
+                    }
+                    self.state = WebSocketState::Closed;
+                    Err(NextMessageError::ConnectionClosed)
+                }
+            },
+        }
+    }
+}
+
+/// The stream of a WebSocket connection, with the first byte of the next frame (which has already
+/// been read) put back in front of it.
+#[derive(Clone)]
+struct Resumed {
+    first: Rc<Cell<Option<u8>>>,
+    stream: TcpStream,
+}
+
+impl Read for Resumed {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.first.take() {
+            Some(first) if !buf.is_empty() => {
+                buf[0] = first;
+                // the frame parser expects more than a single byte from its first read
+                let read = match buf.len() {
+                    1 => 0,
+                    _ => self.stream.read(&mut buf[1..])?,
+                };
+                Ok(1 + read)
+            }
+            first => {
+                self.first.set(first);
+                self.stream.read(buf)
+            }
+        }
+    }
 }
 
 impl Iterator for WebSocket {
@@ -84,35 +223,21 @@ impl Iterator for WebSocket {
 
     fn next(&mut self) -> Option<Result<Message, NextMessageError>> {
         Some(match self.state {
-            WebSocketState::Open => match Message::next(self.stream.clone()) {
-                Ok(msg) => {
-                    if let Message::Ping(ref payload) = msg {
-                        send_frame(
-                            self.stream.clone(),
-                            Frame {
-                                fin: true,
-                                rsv1: false,
-                                rsv2: false,
-                                rsv3: false,
-                                op_code: super::frame::OpCode::Pong,
-                                decoded: payload.clone().unwrap_or_default(),
-                            },
-                        )
-                        .expect("failed to send pong");
-                    }
-                    Ok(msg)
-                }
-                Err(e) => match e {
-                    super::message::DecodeMessageError::ClientProtocolViolationError => {
-                        Err(NextMessageError::ClientError)
-                    }
-                    super::message::DecodeMessageError::ClientSentCloseFrame => {
+            WebSocketState::Open | WebSocketState::Closing => {
+                let first = match self.wait_for_frame() {
+                    Ok(Some(first)) => first,
+                    Ok(None) => {
                         self.state = WebSocketState::Closed;
-                        send_close_frame(self.stream.clone());
-                        Err(NextMessageError::ConnectionClosed)
+                        return Some(Err(NextMessageError::ConnectionClosed));
                     }
-                },
-            },
+                    Err(_) => return Some(Err(NextMessageError::ClientError)),
+                };
+                let stream = Resumed {
+                    first: Rc::new(Cell::new(Some(first))),
+                    stream: self.stream.clone(),
+                };
+                self.next_message(stream)
+            }
             WebSocketState::Closed => Err(NextMessageError::ConnectionClosed),
         })
     }
@@ -129,6 +254,23 @@ pub enum NextMessageError {
     ConnectionClosed,
 }
 
+/// Tells the client that the server is going away (e.g. because it is shutting down), which
+/// should lead the client to close the connection.
+fn send_going_away(stream: impl Write) -> Result<(), SendFrameError> {
+    send_frame(
+        stream,
+        Frame {
+            fin: true,
+            rsv1: false,
+            rsv2: false,
+            rsv3: false,
+            op_code: super::frame::OpCode::Terminate,
+            // the status code 1001 means "going away"
+            decoded: 1001u16.to_be_bytes().to_vec(),
+        },
+    )
+}
+
 fn send_close_frame(stream: impl Write) {
     trace!("Sending close frame");
     send_frame(
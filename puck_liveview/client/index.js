@@ -1,4 +1,4 @@
-let ws = new WebSocket("ws://" + window.location.host + window.location.pathname + "ws");
+let ws = new WebSocket("ws://" + window.location.host + document.currentScript.dataset.ws);
 
 ws.onmessage = (msg) => {
     let data = JSON.parse(msg.data);
@@ -1,9 +1,12 @@
 use std::collections::HashMap;
 
 use lunatic::{Mailbox, Process};
-use puck::ws::{
-    message::Message,
-    websocket::{NextMessageError, WebSocket},
+use puck::{
+    session::SessionRef,
+    ws::{
+        message::Message,
+        websocket::{NextMessageError, WebSocket},
+    },
 };
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
@@ -34,6 +37,7 @@ where
     INPUT: serde::Serialize + serde::de::DeserializeOwned,
 {
     proc_id: Process<INPUT>,
+    session: Option<SessionRef>,
 }
 
 impl<INPUT> Context<INPUT>
@@ -43,6 +47,12 @@ where
     pub fn process(&self) -> Process<INPUT> {
         self.proc_id.clone()
     }
+
+    /// The session of the client, taken from the HTTP request which was upgraded to the
+    /// WebSocket connection (see [manage_with_session]).
+    pub fn session(&self) -> Option<&SessionRef> {
+        self.session.as_ref()
+    }
 }
 
 /// Sets up the provided [Component] for communication over the WebSocket stream. The `Process`
@@ -54,13 +64,35 @@ where
     INPUT: serde::Serialize + serde::de::DeserializeOwned + std::fmt::Debug,
     COMPONENT: Component<DATA, INPUT>,
 {
-    Process::spawn::<(DATA, WebSocket), Mailbox<INPUT>>(
-        (start_data, stream),
-        |(start_data, stream), mailbox| {
+    manage_with_session::<COMPONENT, DATA, INPUT>(start_data, stream, None)
+}
+
+/// Like [manage], but also makes the client's session available to the component through
+/// [Context::session]. Pass the session of the request which was upgraded to the WebSocket
+/// connection, e.g.
+///
+/// ```ignore
+/// let session = req.session().and_then(Session::reference);
+/// let ws = stream.upgrade(&req)?;
+/// manage_with_session::<MyComponent, _, _>(data, ws, session);
+/// ```
+pub fn manage_with_session<COMPONENT, DATA, INPUT>(
+    start_data: DATA,
+    stream: WebSocket,
+    session: Option<SessionRef>,
+) -> Process<INPUT>
+where
+    DATA: serde::Serialize + serde::de::DeserializeOwned,
+    INPUT: serde::Serialize + serde::de::DeserializeOwned + std::fmt::Debug,
+    COMPONENT: Component<DATA, INPUT>,
+{
+    Process::spawn::<(DATA, WebSocket, Option<SessionRef>), Mailbox<INPUT>>(
+        (start_data, stream, session),
+        |(start_data, stream, session), mailbox| {
             // spawn new process
             // todo: maybe have a supervisor
             let process = Process::spawn(
-                (start_data, stream.make_copy()),
+                (start_data, stream.make_copy(), session),
                 main_loop::<COMPONENT, DATA, INPUT>,
             );
 
@@ -88,7 +120,7 @@ where
 }
 
 fn main_loop<COMPONENT, DATA, INPUT>(
-    (start_data, stream): (DATA, WebSocket),
+    (start_data, stream, session): (DATA, WebSocket, Option<SessionRef>),
     mailbox: Mailbox<WsOrInput<INPUT>>,
 ) where
     DATA: serde::Serialize + serde::de::DeserializeOwned,
@@ -100,6 +132,7 @@ fn main_loop<COMPONENT, DATA, INPUT>(
             WsOrInput::WhoAmI(p) => p,
             _ => unreachable!(),
         },
+        session,
     };
 
     let mut component = COMPONENT::new(start_data, &context);
@@ -1,30 +1,34 @@
-use puck::{body::Body, Response};
+use puck::{body::Body, Request, Response};
 
 /// Returns the index page to the client.
 ///
-/// You can mount this anywhere, but make sure that you mount an instance of `js` at
-/// `/<path of this route>/js`.
-pub fn index() -> Response {
+/// The page loads the JS from `js` and connects to the WebSocket at `ws`, relative to the path
+/// at which the router handling `req` is mounted (see `Router::nest`). This means that you can
+/// mount the liveview anywhere, as long as this route, an instance of [js] at `/js` and the
+/// WebSocket at `/ws` are all added to the same router.
+pub fn index(req: &Request) -> Response {
+    let base = req.base_path().replace('&', "&amp;").replace('"', "&quot;");
     Response::build()
         .header("Content-Type", "text/html")
-        .body(Body::from_string(
+        .body(Body::from_string(format!(
             r#"
         <!DOCTYPE html>
         <html>
             <head>
-                <script src="./js"></script>
+                <script src="{base}/js" data-ws="{base}/ws"></script>
             </head>
             <body>
             </body>
         </html>
         "#,
-        ))
+            base = base
+        )))
         .build()
 }
 
 /// Returns the JS needed for the application to the client.
 ///
-/// You need to mount this at <wherever you mounted the HTML file>/js
+/// You need to mount this at `/js` in the same router as [index].
 pub fn js() -> Response {
     Response::build()
         .header("Content-Type", "application/javascript")